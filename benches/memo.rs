@@ -0,0 +1,77 @@
+#[macro_use]
+extern crate criterion;
+
+extern crate lax;
+
+use criterion::Criterion;
+
+use lax::advancer::*;
+use lax::memo::*;
+
+const EXPR: usize = 0;
+const TERM: usize = 1;
+
+// expr := term '+' expr / term '-' expr / term
+// term := '(' expr ')' / 'x'
+//
+// Deeply nested parens make every failed alternative re-parse the whole
+// inner term, which is exponential without memoization.
+fn expr(a: &mut Advancer<char>, memo: &mut Memo<()>) -> Option<()> {
+   memo.rule(a, EXPR, |a, memo| {
+      let pos = a.pos();
+
+      for op in &['+', '-'] {
+         if term(a, memo).is_some() && a.one(*op).is_some() && expr(a, memo).is_some() {
+            return Some(());
+         }
+         a.seek(pos);
+      }
+
+      term(a, memo)
+   })
+}
+
+fn term(a: &mut Advancer<char>, memo: &mut Memo<()>) -> Option<()> {
+   memo.rule(a, TERM, |a, memo| {
+      let pos = a.pos();
+
+      if a.one('(').is_some() {
+         expr(a, memo)?;
+         a.one(')')?;
+         return Some(());
+      }
+
+      a.seek(pos);
+      a.one('x')?;
+      Some(())
+   })
+}
+
+fn chars(depth: usize) -> Vec<char> {
+   format!("{}x{}", "(".repeat(depth), ")".repeat(depth))
+      .chars()
+      .collect()
+}
+
+fn memo_benchmark(c: &mut Criterion) {
+   c.bench_function("nested_memo", |b| {
+      let chars = chars(10);
+      b.iter(|| {
+         let mut advancer = Advancer::new(&chars);
+         expr(&mut advancer, &mut Memo::new()).unwrap();
+         assert_eq!(advancer.pos(), chars.len());
+      })
+   });
+
+   c.bench_function("nested_no_memo", |b| {
+      let chars = chars(10);
+      b.iter(|| {
+         let mut advancer = Advancer::new(&chars);
+         expr(&mut advancer, &mut Memo::disabled()).unwrap();
+         assert_eq!(advancer.pos(), chars.len());
+      })
+   });
+}
+
+criterion_group!(memo_group, memo_benchmark);
+criterion_main!(memo_group);
@@ -3,7 +3,7 @@ extern crate criterion;
 
 extern crate lax;
 
-use criterion::Criterion;
+use criterion::{Criterion, Throughput};
 
 use std::fs::File;
 use std::io::prelude::*;
@@ -23,17 +23,22 @@ fn tokenize_benchmark(c: &mut Criterion) {
 
    assert!(count * source.len() >= min && (count - 1) * source.len() < min);
 
-   let input = iter::repeat(source)
-      .take(count)
-      .collect::<Vec<_>>()
-      .join("\n");
+   let input = iter::repeat_n(source, count).collect::<Vec<_>>().join("\n");
 
    let lines = input.matches('\n').count();
    println!("{} lines/{} kb", lines, input.len() / 1024);
 
    let chars: Vec<_> = input.chars().collect();
 
-   c.bench_function("tokenize", move |b| b.iter(|| tokenize(&chars)));
+   let mut group = c.benchmark_group("tokenize");
+
+   group.throughput(Throughput::Bytes(input.len() as u64));
+
+   group.bench_function("chars", |b| b.iter(|| tokenize(&chars)));
+
+   group.bench_function("str", |b| b.iter(|| tokenize_str(&input)));
+
+   group.finish();
 }
 
 criterion_group!(tokenize_group, tokenize_benchmark);
@@ -3,12 +3,13 @@ extern crate lax;
 use std::fs::File;
 use std::io::prelude::*;
 
-use lax::indentation::estimate_indentation;
+use lax::blocks::BlockTree;
+use lax::indentation::IndentationEstimator;
 use lax::tokenize::*;
 
 macro_rules! printi {
    ($fmt:expr, $pos:expr, $($arg:tt)*) => {
-      println!(concat!("[{:03}] ", $fmt), $pos, $($arg)*);
+      println!(concat!("[{:03}] ", $fmt), $pos, $($arg)*)
    };
 }
 
@@ -45,9 +46,30 @@ fn main() {
 
    println!("----------------");
 
-   let module_indentation = estimate_indentation(&toks, &toks_meta, &line_starts);
+   let estimate = IndentationEstimator::new()
+      .count(&toks, &toks_meta, &line_starts)
+      .estimate();
 
-   println!("Indentation: {}", module_indentation);
+   println!(
+      "Indentation: {} (confidence {:.2})",
+      estimate.indentation, estimate.confidence
+   );
+
+   if let Some((alternative, _)) = estimate.runner_up() {
+      println!("Runner-up: {}", alternative);
+   }
 
    println!("================");
+
+   let tree = BlockTree::new(&toks, &toks_meta, &line_starts, estimate.indentation);
+
+   tree.lines().iter().enumerate().for_each(|(i, line)| {
+      printi!(
+         "depth: {} parent: {:?} end: {}",
+         i,
+         line.depth,
+         line.parent,
+         line.end
+      )
+   });
 }
@@ -2,20 +2,15 @@ extern crate lax;
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::usize;
 
-use lax::indentation::estimate_indentation;
+use lax::grammar::{analyze, Builder, ElementId, TokParser, Tree};
+use lax::indentation::module_indentation;
 use lax::tokenize::*;
-
-macro_rules! dsp_elm {
-   ($elm_pos:expr, $path:expr, $fmt:expr, $($arg:tt)*) => {
-      printi!(concat!("{}. ", $fmt), $elm_pos, "..".repeat($path.len()), $($arg)*);
-   };
-}
+use lax::trace::Printer;
 
 macro_rules! printi {
    ($fmt:expr, $pos:expr, $($arg:tt)*) => {
-      println!(concat!("[{:03}] ", $fmt), $pos, $($arg)*);
+      println!(concat!("[{:03}] ", $fmt), $pos, $($arg)*)
    };
 }
 
@@ -42,6 +37,10 @@ fn main() {
 
    let (instructions, elements) = builder.destructure();
 
+   for warning in analyze(&instructions, &elements, Element::Module) {
+      println!("Warning: {:?}", warning);
+   }
+
    println!("----------------");
 
    instructions
@@ -86,19 +85,47 @@ fn main() {
 
    println!("----------------");
 
-   let module_indentation = estimate_indentation(&toks, &toks_meta, &line_starts);
+   let module_indentation =
+      module_indentation(&chars, &toks, &toks_meta, &line_starts, None).indentation;
 
    println!("Indentation: {}", module_indentation);
 
    println!("================");
 
-   TokParser::new(
+   let mut printer = Printer::stdout();
+
+   let tree = TokParser::new(
       &instructions,
       &elements,
+      Element::Module,
       &toks,
       &toks_meta,
       module_indentation,
-   ).parse();
+   )
+   .with_trace(&mut printer)
+   .parse();
+
+   println!("================");
+
+   match tree {
+      Some(tree) => print_tree(&tree, 0),
+      None => println!("No match"),
+   }
+}
+
+fn print_tree(tree: &Tree<Element>, depth: usize) {
+   printi!(
+      "{}{:?} [{:03}-{:03}]",
+      tree.start,
+      "..".repeat(depth),
+      tree.element,
+      tree.start,
+      tree.end
+   );
+
+   for child in &tree.children {
+      print_tree(child, depth + 1);
+   }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -118,10 +145,14 @@ enum Element {
    Number,
 }
 
-const ELEMENTS_COUNT: usize = Element::Number as usize + 1;
+impl ElementId for Element {
+   fn index(self) -> usize {
+      self as usize
+   }
+}
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn module(b: &mut Builder) {
+#[rustfmt::skip]
+fn module(b: &mut Builder<Element>) {
    b.element(Element::Module)
       .zero_or_more()
          .choice()
@@ -131,8 +162,8 @@ fn module(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn if_(b: &mut Builder) {
+#[rustfmt::skip]
+fn if_(b: &mut Builder<Element>) {
    b.element(Element::If)
       .sequence()
          .tok(Tok::If)
@@ -143,9 +174,9 @@ fn if_(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn block(b: &mut Builder) {
-   b.element(Element::Block)
+#[rustfmt::skip]
+fn block(b: &mut Builder<Element>) {
+   b.block(Element::Block)
       .sequence()
          .zero_or_more()
             .tok(Tok::LineEnd)
@@ -160,8 +191,8 @@ fn block(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn statement(b: &mut Builder) {
+#[rustfmt::skip]
+fn statement(b: &mut Builder<Element>) {
    b.element(Element::Statement)
       .sequence()
          .indentation(0)
@@ -175,8 +206,8 @@ fn statement(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn expression(b: &mut Builder) {
+#[rustfmt::skip]
+fn expression(b: &mut Builder<Element>) {
    b.element(Element::Expression)
       .sequence()
          .reference(Element::Nullary)
@@ -186,8 +217,8 @@ fn expression(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn nary_right(b: &mut Builder) {
+#[rustfmt::skip]
+fn nary_right(b: &mut Builder<Element>) {
    b.element(Element::NaryRight)
       .sequence()
          .skip_space()
@@ -197,8 +228,8 @@ fn nary_right(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn nary_operator(b: &mut Builder) {
+#[rustfmt::skip]
+fn nary_operator(b: &mut Builder<Element>) {
    b.element(Element::NaryOperator)
       .choice()
          .tok(Tok::Plus)
@@ -210,8 +241,8 @@ fn nary_operator(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn nullary(b: &mut Builder) {
+#[rustfmt::skip]
+fn nullary(b: &mut Builder<Element>) {
    b.element(Element::Nullary)
       .choice()
          .reference(Element::Parens)
@@ -222,8 +253,8 @@ fn nullary(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn not(b: &mut Builder) {
+#[rustfmt::skip]
+fn not(b: &mut Builder<Element>) {
    b.element(Element::Not)
       .sequence()
          .tok(Tok::Not)
@@ -232,8 +263,8 @@ fn not(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn parens(b: &mut Builder) {
+#[rustfmt::skip]
+fn parens(b: &mut Builder<Element>) {
    b.element(Element::Parens)
       .sequence()
          .tok(Tok::ParenLeft)
@@ -244,16 +275,16 @@ fn parens(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn identifier(b: &mut Builder) {
+#[rustfmt::skip]
+fn identifier(b: &mut Builder<Element>) {
    b.element(Element::Identifier)
       .sequence()
          .tok(Tok::Identifier)
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn boolean(b: &mut Builder) {
+#[rustfmt::skip]
+fn boolean(b: &mut Builder<Element>) {
    b.element(Element::Boolean)
       .choice()
          .tok(Tok::True)
@@ -261,8 +292,8 @@ fn boolean(b: &mut Builder) {
       .end();
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-fn number(b: &mut Builder) {
+#[rustfmt::skip]
+fn number(b: &mut Builder<Element>) {
    b.element(Element::Number)
       .choice()
          .sequence()
@@ -280,476 +311,3 @@ fn number(b: &mut Builder) {
          .end()
       .end();
 }
-
-#[derive(Debug)]
-enum Instruction {
-   Element(Element),
-   Reference(Element),
-   Tok(Tok),
-   Sequence(usize),
-   Choice(usize),
-   ZeroOrOne(usize),
-   ZeroOrMore(usize),
-   Indentation(usize),
-}
-
-struct Builder {
-   instructions: Vec<Instruction>,
-   starts: Vec<usize>,
-   elements: [usize; ELEMENTS_COUNT],
-}
-
-impl Builder {
-   fn new() -> Self {
-      Builder {
-         instructions: Vec::new(),
-         starts: Vec::new(),
-         elements: [0; ELEMENTS_COUNT],
-      }
-   }
-
-   fn destructure(self) -> (Vec<Instruction>, [usize; ELEMENTS_COUNT]) {
-      let Self {
-         instructions,
-         elements,
-         ..
-      } = self;
-      (instructions, elements)
-   }
-
-   fn element(&mut self, element: Element) -> &mut Self {
-      self.elements[element as usize] = self.instructions.len();
-      self.instructions.push(Instruction::Element(element));
-      self
-   }
-
-   #[cfg_attr(rustfmt, rustfmt_skip)]
-   fn skip_space(&mut self) -> &mut Self {
-      self
-         .zero_or_one()
-            .choice()
-               .tok(Tok::Space)
-               .sequence()
-                  .tok(Tok::LineEnd)
-                  .indentation(2)
-               .end()
-            .end()
-         .end()
-   }
-
-   fn indentation(&mut self, indentation: usize) -> &mut Self {
-      self
-         .instructions
-         .push(Instruction::Indentation(indentation));
-      self
-   }
-
-   fn reference(&mut self, element: Element) -> &mut Self {
-      self.instructions.push(Instruction::Reference(element));
-      self
-   }
-
-   fn tok(&mut self, tok: Tok) -> &mut Self {
-      self.instructions.push(Instruction::Tok(tok));
-      self
-   }
-
-   fn sequence(&mut self) -> &mut Self {
-      self.start(Instruction::Sequence(0));
-      self
-   }
-
-   fn choice(&mut self) -> &mut Self {
-      self.start(Instruction::Choice(0));
-      self
-   }
-
-   fn zero_or_one(&mut self) -> &mut Self {
-      self.start(Instruction::ZeroOrOne(0));
-      self
-   }
-
-   fn zero_or_more(&mut self) -> &mut Self {
-      self.start(Instruction::ZeroOrMore(0));
-      self
-   }
-
-   fn start(&mut self, parent: Instruction) -> &mut Self {
-      self.starts.push(self.instructions.len());
-      self.instructions.push(parent);
-      self
-   }
-
-   fn end(&mut self) -> &mut Self {
-      debug_assert!(!self.starts.is_empty());
-
-      let start = self.starts.pop().unwrap();
-
-      debug_assert!(self.instructions.len() > start);
-
-      let end = self.instructions.len();
-
-      match *unsafe { self.instructions.get_unchecked_mut(start) } {
-         Instruction::Sequence(ref mut i)
-         | Instruction::Choice(ref mut i)
-         | Instruction::ZeroOrOne(ref mut i)
-         | Instruction::ZeroOrMore(ref mut i) => *i = end,
-         _ => unreachable!(),
-      }
-
-      self
-   }
-}
-
-struct TokParser<'b, 't> {
-   instructions: &'b [Instruction],
-   elements: &'b [usize; ELEMENTS_COUNT],
-   toks: &'t [Tok],
-   toks_meta: &'t [TokMeta],
-   module_indentation: usize,
-   current_indentation: usize,
-   path: Vec<usize>,
-   elm_pos: usize,
-   tok_pos: usize,
-   tok_pos_stack: Vec<usize>,
-   matched: bool,
-}
-
-impl<'b, 't> TokParser<'b, 't> {
-   fn new(
-      instructions: &'b [Instruction],
-      elements: &'b [usize; ELEMENTS_COUNT],
-      toks: &'t [Tok],
-      toks_meta: &'t [TokMeta],
-      module_indentation: usize,
-   ) -> Self {
-      let path: Vec<usize> = Vec::new();
-      let elm_pos = elements[Element::Module as usize];
-
-      let tok_pos = 0;
-      let tok_pos_stack: Vec<usize> = Vec::new();
-
-      let current_indentation = 0;
-
-      let matched = false;
-
-      TokParser {
-         instructions,
-         elements,
-         toks,
-         toks_meta,
-         module_indentation,
-         current_indentation,
-         path,
-         elm_pos,
-         tok_pos,
-         tok_pos_stack,
-         matched,
-      }
-   }
-
-   fn parse(&mut self) {
-      loop {
-         if self.process_next() && self.try_finalize() {
-            break;
-         }
-      }
-   }
-
-   fn process_next(&mut self) -> bool {
-      dsp_elm!(
-         self.elm_pos,
-         self.path,
-         "{:?}",
-         self.instructions[self.elm_pos]
-      );
-
-      match self.instructions[self.elm_pos] {
-         Instruction::Element(ref element) => self.process_element(element),
-         Instruction::Sequence(end)
-         | Instruction::ZeroOrOne(end)
-         | Instruction::ZeroOrMore(end)
-         | Instruction::Choice(end) => self.process_list(end),
-         Instruction::Reference(ref element) => self.process_reference(element),
-         Instruction::Tok(ref tok) => self.process_tok(tok),
-         Instruction::Indentation(indentation) => self.process_indentation(indentation),
-      }
-   }
-
-   fn process_element(&mut self, element: &Element) -> bool {
-      dsp_elm!(
-         self.elm_pos,
-         self.path,
-         "{:?} [{:03}] >>",
-         element,
-         self.tok_pos
-      );
-      if element == &Element::Block {
-         self.current_indentation += 1;
-      }
-      self.path.push(self.elm_pos);
-      self.tok_pos_stack.push(self.tok_pos);
-      self.elm_pos += 1;
-
-      false
-   }
-
-   fn process_list(&mut self, end: usize) -> bool {
-      debug_assert!(self.elm_pos < end);
-      self.path.push(self.elm_pos);
-      self.tok_pos_stack.push(self.tok_pos);
-      self.elm_pos += 1;
-
-      false
-   }
-
-   fn process_reference(&mut self, element: &Element) -> bool {
-      if self.tok_pos == self.toks.len() {
-         self.matched = false;
-         self.elm_pos += 1;
-
-         true
-      } else {
-         self.path.push(self.elm_pos);
-         self.elm_pos = self.elements[*element as usize];
-
-         false
-      }
-   }
-
-   fn process_tok(&mut self, tok: &Tok) -> bool {
-      self.matched = if let Some(tok_src) = self.toks.get(self.tok_pos) {
-         let equal = tok == tok_src;
-         if equal {
-            dsp_elm!(
-               self.elm_pos,
-               self.path,
-               "TOK {:?} [{:03}]",
-               tok_src,
-               self.tok_pos
-            );
-
-            self.tok_pos += 1;
-         } else {
-            dsp_elm!(
-               self.elm_pos,
-               self.path,
-               "TOK {:?} [{:03}]",
-               tok_src,
-               self.tok_pos
-            );
-         }
-         equal
-      } else {
-         false
-      };
-
-      self.elm_pos += 1;
-
-      true
-   }
-
-   fn process_indentation(&mut self, indentation: usize) -> bool {
-      let spaces = (self.current_indentation + indentation) * self.module_indentation;
-
-      self.matched = if spaces == 0 {
-         dsp_elm!(self.elm_pos, self.path, "Indentation {}", 0);
-         true
-      } else if let Some(tok_src) = self.toks.get(self.tok_pos) {
-         let span = self.toks_meta[self.tok_pos].span;
-         let equal = tok_src == &Tok::Space && span == spaces;
-         if equal {
-            dsp_elm!(
-               self.elm_pos,
-               self.path,
-               "Indentation {} [{:03}]",
-               span,
-               self.tok_pos
-            );
-
-            self.tok_pos += 1;
-         } else {
-            dsp_elm!(
-               self.elm_pos,
-               self.path,
-               "Indentation {} [{:03}]",
-               span,
-               self.tok_pos
-            );
-         }
-
-         equal
-      } else {
-         false
-      };
-
-      self.elm_pos += 1;
-
-      true
-   }
-
-   fn try_finalize(&mut self) -> bool {
-      loop {
-         if !self.path.is_empty() {
-            let pos = *self.path.last().unwrap();
-
-            dsp_elm!(
-               pos,
-               self.path,
-               "?? {:?} [{:03}] {}",
-               self.instructions[pos],
-               self.elm_pos,
-               self.matched
-            );
-
-            let exit = match self.instructions[pos] {
-               Instruction::Element(ref element) => self.try_finalize_element(element, pos),
-               Instruction::Reference(ref element) => self.try_finalize_reference(element, pos),
-               Instruction::Sequence(end) => self.try_finalize_sequence(end, pos),
-               Instruction::Choice(end) => self.try_finalize_choice(end, pos),
-               Instruction::ZeroOrOne(end) => self.try_finalize_zero_or_one(end, pos),
-               Instruction::ZeroOrMore(end) => self.try_finalize_zero_or_more(end, pos),
-               _ => unreachable!(),
-            };
-
-            if exit {
-               return false;
-            }
-         } else {
-            dsp_elm!(self.elm_pos, self.path, "{}", "FI");
-            return true;
-         }
-      }
-   }
-
-   fn try_finalize_element(&mut self, element: &Element, pos: usize) -> bool {
-      if element == &Element::Block {
-         self.current_indentation -= 1;
-      }
-      self.path.pop();
-      let element_tok_pos = self.pop_tok_pos();
-      if self.matched {
-         dsp_elm!(
-            pos,
-            self.path,
-            "{:?} [{:03}-{:03}] <<",
-            element,
-            element_tok_pos,
-            self.tok_pos
-         );
-      } else {
-         debug_assert!(element_tok_pos == self.tok_pos);
-         dsp_elm!(
-            pos,
-            self.path,
-            "{:?} [{:03}-{:03}] <<",
-            element,
-            element_tok_pos,
-            self.tok_pos
-         );
-      }
-
-      false
-   }
-
-   fn try_finalize_reference(&mut self, _element: &Element, pos: usize) -> bool {
-      dsp_elm!(self.elm_pos, self.path, "{} &> {}", self.elm_pos, pos + 1);
-      self.elm_pos = pos + 1;
-      self.path.pop();
-
-      false
-   }
-
-   fn try_finalize_sequence(&mut self, end: usize, pos: usize) -> bool {
-      if !self.matched {
-         dsp_elm!(pos, self.path, "{} -> {}", self.elm_pos, end);
-         self.elm_pos = end;
-         self.path.pop();
-         self.tok_pos = self.pop_tok_pos();
-
-         false
-      } else if self.elm_pos == end {
-         self.path.pop();
-         self.tok_pos_stack.pop();
-
-         false
-      } else {
-         true
-      }
-   }
-
-   fn try_finalize_choice(&mut self, end: usize, pos: usize) -> bool {
-      if self.matched {
-         dsp_elm!(pos, self.path, "{} |> {}", self.elm_pos, end);
-         self.elm_pos = end;
-         self.path.pop();
-         self.tok_pos_stack.pop();
-
-         false
-      } else if self.elm_pos == end {
-         self.path.pop();
-         self.tok_pos = self.pop_tok_pos();
-
-         false
-      } else {
-         self.tok_pos = self.last_tok_pos();
-
-         true
-      }
-   }
-
-   fn try_finalize_zero_or_one(&mut self, end: usize, _pos: usize) -> bool {
-      if !self.matched {
-         dsp_elm!(self.elm_pos, self.path, "{} 0> {}", self.elm_pos, end);
-         self.elm_pos = end;
-         self.path.pop();
-         self.tok_pos = self.pop_tok_pos();
-         self.matched = true;
-
-         false
-      } else if self.elm_pos == end {
-         self.path.pop();
-         self.tok_pos_stack.pop();
-
-         false
-      } else {
-         true
-      }
-   }
-
-   fn try_finalize_zero_or_more(&mut self, end: usize, pos: usize) -> bool {
-      if !self.matched {
-         dsp_elm!(self.elm_pos, self.path, "{} *> {}", self.elm_pos, end);
-         self.elm_pos = end;
-         self.path.pop();
-         self.tok_pos = self.pop_tok_pos();
-         self.matched = true;
-
-         false
-      } else if self.elm_pos == end {
-         dsp_elm!(self.elm_pos, self.path, "{} <* {}", self.elm_pos, pos);
-         self.elm_pos = pos + 1;
-         *self.tok_pos_stack.last_mut().unwrap() = self.tok_pos;
-
-         true
-      } else {
-         true
-      }
-   }
-
-   fn last_tok_pos(&self) -> usize {
-      if let Some(tok_pos) = self.tok_pos_stack.last() {
-         *tok_pos
-      } else {
-         unreachable!();
-      }
-   }
-
-   fn pop_tok_pos(&mut self) -> usize {
-      if let Some(tok_pos) = self.tok_pos_stack.pop() {
-         tok_pos
-      } else {
-         unreachable!();
-      }
-   }
-}
@@ -0,0 +1,53 @@
+extern crate lax;
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::process;
+
+use lax::indentation::module_indentation;
+use lax::reindent::{apply, reindent, ReindentError};
+use lax::tokenize::*;
+
+fn main() {
+   let args: Vec<_> = env::args().collect();
+
+   let to = match args.get(2).map(|to| to.parse()) {
+      Some(Ok(to)) if args.len() == 3 && to > 0 => to,
+      _ => {
+         eprintln!("Usage: reindent <file> <indentation>");
+         eprintln!("<indentation> is the new unit, a positive number of spaces");
+         process::exit(1);
+      }
+   };
+
+   let mut f = File::open(&args[1]).expect("file not found");
+
+   let mut source = String::new();
+   f.read_to_string(&mut source)
+      .expect("something went wrong reading the file");
+
+   let chars: Vec<_> = source.chars().collect();
+
+   let (toks, toks_meta, line_starts) = tokenize(&chars);
+
+   let from = module_indentation(&chars, &toks, &toks_meta, &line_starts, None).indentation;
+
+   let reindent = match reindent(&chars, &toks, &toks_meta, &line_starts, from, to) {
+      Ok(reindent) => reindent,
+      Err(ReindentError::ZeroFrom) => {
+         eprintln!("Cannot reindent: the indentation of the file is zero spaces");
+         process::exit(1);
+      }
+      Err(ReindentError::ZeroTo) => unreachable!(),
+   };
+
+   for diagnostic in &reindent.skipped {
+      eprintln!(
+         "Skipped line {}: indentation is not a multiple of {}",
+         diagnostic.line, from
+      );
+   }
+
+   print!("{}", apply(&chars, &reindent.edits));
+}
@@ -1,8 +1,73 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+   pub offset: usize,
+   pub line: usize,
+   pub col: usize,
+}
+
+/// Offsets of the first character of every line, recognizing `\n`, `\r\n`
+/// and a lone `\r` as line breaks.
+#[derive(Debug, Clone)]
+pub struct Lines {
+   starts: Vec<usize>,
+}
+
+impl Lines {
+   pub fn new(chars: &[char]) -> Self {
+      let mut starts = vec![0];
+
+      let mut i = 0;
+      while i < chars.len() {
+         match chars[i] {
+            '\n' => starts.push(i + 1),
+            '\r' => {
+               if chars.get(i + 1) == Some(&'\n') {
+                  i += 1;
+               }
+               starts.push(i + 1);
+            }
+            _ => {}
+         }
+         i += 1;
+      }
+
+      Lines { starts }
+   }
+
+   pub fn starts(&self) -> &[usize] {
+      &self.starts
+   }
+
+   pub fn location(&self, offset: usize) -> Location {
+      let index = match self.starts.binary_search(&offset) {
+         Ok(index) => index,
+         Err(index) => index - 1,
+      };
+
+      Location {
+         offset,
+         line: index + 1,
+         col: offset - self.starts[index] + 1,
+      }
+   }
+}
+
 #[derive(Clone)]
 pub struct Advancer<'s, T: 's> {
    slice: &'s [T],
    start: usize,
    peek: usize,
+   lines: Option<Lines>,
+   trivia: Option<fn(&T) -> bool>,
+}
+
+impl<'s> Advancer<'s, char> {
+   pub fn with_lines(slice: &'s [char]) -> Self {
+      Advancer {
+         lines: Some(Lines::new(slice)),
+         ..Advancer::new(slice)
+      }
+   }
 }
 
 impl<'s, T> Advancer<'s, T> {
@@ -11,6 +76,17 @@ impl<'s, T> Advancer<'s, T> {
          slice,
          start: 0,
          peek: 0,
+         lines: None,
+         trivia: None,
+      }
+   }
+
+   /// Items for which `trivia` returns `true` are transparently skipped by
+   /// all matchers and by `peek_nth`.
+   pub fn with_trivia(slice: &'s [T], trivia: fn(&T) -> bool) -> Self {
+      Advancer {
+         trivia: Some(trivia),
+         ..Advancer::new(slice)
       }
    }
 
@@ -18,10 +94,31 @@ impl<'s, T> Advancer<'s, T> {
       self.peek
    }
 
+   /// Line and column of `pos`, available when created with `with_lines`.
+   pub fn location(&self, pos: usize) -> Option<Location> {
+      debug_assert!(self.slice.len() >= pos);
+      self.lines.as_ref().map(|lines| lines.location(pos))
+   }
+
+   /// Position of the next item that is not trivia.
+   pub fn next_pos(&self) -> usize {
+      self.skip_trivia(self.peek)
+   }
+
    pub fn current(&self) -> &'s [T] {
       &self.slice[self.start..self.peek]
    }
 
+   /// The `n`-th upcoming item after the peek position, zero based and not
+   /// counting trivia.
+   pub fn peek_nth(&self, n: usize) -> Option<&'s T> {
+      let mut pos = self.skip_trivia(self.peek);
+      for _ in 0..n {
+         pos = self.skip_trivia(pos + 1);
+      }
+      self.slice.get(pos)
+   }
+
    pub fn advance(&mut self, pos: usize) {
       debug_assert!(self.slice.len() >= pos);
       debug_assert!(pos > self.peek);
@@ -29,6 +126,14 @@ impl<'s, T> Advancer<'s, T> {
       self.start = pos;
    }
 
+   /// Moves the peek position anywhere between the start and the end of
+   /// the slice, used for backtracking within the current span.
+   pub fn seek(&mut self, pos: usize) {
+      debug_assert!(self.slice.len() >= pos);
+      debug_assert!(pos >= self.start);
+      self.peek = pos;
+   }
+
    pub fn reset(&mut self) {
       self.peek = self.start;
    }
@@ -41,18 +146,19 @@ impl<'s, T> Advancer<'s, T> {
 
    pub fn completed(&self) -> bool {
       debug_assert!(self.peek == self.start);
-      self.peek == self.slice.len()
+      self.skip_trivia(self.peek) == self.slice.len()
    }
 
    pub fn cannot_peek(&self) -> bool {
-      self.peek == self.slice.len()
+      self.skip_trivia(self.peek) == self.slice.len()
    }
 
-   #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+   #[allow(clippy::needless_pass_by_value)]
    pub fn one<M: Matcher<T>>(&mut self, m: M) -> Option<&T> {
-      if let Some(item) = self.slice.get(self.peek) {
+      let pos = self.skip_trivia(self.peek);
+      if let Some(item) = self.slice.get(pos) {
          if m.matches(item) {
-            self.peek += 1;
+            self.peek = pos + 1;
             return Some(item);
          }
       }
@@ -61,29 +167,22 @@ impl<'s, T> Advancer<'s, T> {
       None
    }
 
-   #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+   #[allow(clippy::needless_pass_by_value)]
    pub fn zero_or_one<M: Matcher<T>>(&mut self, m: M) {
-      if let Some(item) = self.slice.get(self.peek) {
+      let pos = self.skip_trivia(self.peek);
+      if let Some(item) = self.slice.get(pos) {
          if m.matches(item) {
-            self.peek += 1;
+            self.peek = pos + 1;
          }
       }
    }
 
-   #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+   #[allow(clippy::needless_pass_by_value)]
    pub fn one_or_more<M: Matcher<T>>(&mut self, m: M) -> Option<()> {
-      debug_assert!(self.peek <= self.slice.len());
-
-      let mut span = 0;
-      for item in unsafe { self.slice.get_unchecked(self.peek..) } {
-         if !m.matches(item) {
-            break;
-         }
-         span += 1;
-      }
+      let end = self.repeat(&m);
 
-      if span > 0 {
-         self.peek += span;
+      if end != self.peek {
+         self.peek = end;
          Some(())
       } else {
          self.reset();
@@ -91,26 +190,52 @@ impl<'s, T> Advancer<'s, T> {
       }
    }
 
-   #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+   #[allow(clippy::needless_pass_by_value)]
    pub fn zero_or_more<M: Matcher<T>>(&mut self, m: M) {
+      self.peek = self.repeat(&m);
+   }
+
+   fn skip_trivia(&self, mut pos: usize) -> usize {
+      if let Some(trivia) = self.trivia {
+         while let Some(item) = self.slice.get(pos) {
+            if !trivia(item) {
+               break;
+            }
+            pos += 1;
+         }
+      }
+
+      pos
+   }
+
+   fn repeat<M: Matcher<T>>(&self, m: &M) -> usize {
       debug_assert!(self.peek <= self.slice.len());
 
-      let mut span = 0;
-      for item in unsafe { self.slice.get_unchecked(self.peek..) } {
-         if !m.matches(item) {
-            break;
+      if self.trivia.is_none() {
+         let mut span = 0;
+         for item in unsafe { self.slice.get_unchecked(self.peek..) } {
+            if !m.matches(item) {
+               break;
+            }
+            span += 1;
          }
-         span += 1;
+
+         return self.peek + span;
       }
 
-      if span != 0 {
-         self.peek += span;
+      let mut end = self.peek;
+      loop {
+         let pos = self.skip_trivia(end);
+         match self.slice.get(pos) {
+            Some(item) if m.matches(item) => end = pos + 1,
+            _ => return end,
+         }
       }
    }
 }
 
 pub trait Matcher<T> {
-   fn matches(&self, &T) -> bool;
+   fn matches(&self, item: &T) -> bool;
 }
 
 impl<T> Matcher<T> for T
@@ -128,7 +253,7 @@ impl<T> Matcher<T> for fn(&T) -> bool {
    }
 }
 
-impl<'a, T> Matcher<T> for &'a [T]
+impl<T> Matcher<T> for &[T]
 where
    T: PartialEq,
 {
@@ -155,4 +280,53 @@ mod tests {
       let mut advancer = Advancer::new(&slice);
       let _ = advancer.consume();
    }
+
+   fn location(source: &str, pos: usize) -> (usize, usize) {
+      let slice: Vec<_> = source.chars().collect();
+      let location = Advancer::with_lines(&slice).location(pos).unwrap();
+      assert_eq!(location.offset, pos);
+      (location.line, location.col)
+   }
+
+   #[test]
+   fn test_location() {
+      assert_eq!(location("", 0), (1, 1));
+      assert_eq!(location("abc", 2), (1, 3));
+      assert_eq!(location("ab\ncd", 2), (1, 3));
+      assert_eq!(location("ab\ncd", 3), (2, 1));
+      assert_eq!(location("ab\r\ncd", 3), (1, 4));
+      assert_eq!(location("ab\r\ncd", 5), (2, 2));
+      assert_eq!(location("ab\rcd", 4), (2, 2));
+      assert_eq!(location("\r\r\n\n", 4), (4, 1));
+      assert_eq!(location("a\n\rb", 3), (3, 1));
+   }
+
+   fn is_space(c: &char) -> bool {
+      *c == ' '
+   }
+
+   #[test]
+   fn test_trivia() {
+      let slice: Vec<_> = " a  a b c  ".chars().collect();
+      let mut advancer = Advancer::with_trivia(&slice, is_space);
+      assert_eq!(advancer.peek_nth(0), Some(&'a'));
+      assert_eq!(advancer.peek_nth(2), Some(&'b'));
+      assert_eq!(advancer.peek_nth(4), None);
+      assert_eq!(advancer.one('a'), Some(&'a'));
+      assert_eq!(advancer.pos(), 2);
+      advancer.zero_or_more('a');
+      assert_eq!(advancer.pos(), 5);
+      advancer.zero_or_one('c');
+      assert_eq!(advancer.pos(), 5);
+      assert_eq!(advancer.one_or_more(&['b', 'c'] as &[char]), Some(()));
+      assert_eq!(advancer.current(), &slice[..9]);
+      advancer.consume();
+      assert!(advancer.completed());
+   }
+
+   #[test]
+   fn test_location_untracked() {
+      let slice: Vec<_> = "a\nb".chars().collect();
+      assert_eq!(Advancer::new(&slice).location(2), None);
+   }
 }
@@ -0,0 +1,237 @@
+use tokenize::TokMeta;
+
+/// Range of token indices, `start` inclusive and `end` exclusive, indexing
+/// both the token stream and its `TokMeta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+   pub start: usize,
+   pub end: usize,
+}
+
+impl Span {
+   pub fn new(start: usize, end: usize) -> Self {
+      debug_assert!(start <= end);
+      Span { start, end }
+   }
+
+   /// Span covering both `self` and `other`.
+   pub fn to(self, other: Span) -> Span {
+      Span::new(self.start, other.end)
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.start == self.end
+   }
+
+   /// Meta of the first token, giving the line and column the node starts
+   /// at.
+   pub fn meta<'m>(&self, toks_meta: &'m [TokMeta]) -> &'m TokMeta {
+      &toks_meta[self.start]
+   }
+
+   /// Source text the span covers.
+   pub fn text(&self, chars: &[char], toks_meta: &[TokMeta]) -> String {
+      if self.is_empty() {
+         return String::new();
+      }
+
+      let first = &toks_meta[self.start];
+      let last = &toks_meta[self.end - 1];
+
+      chars[first.end - first.span..last.end].iter().collect()
+   }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+   pub body: Vec<Stmt>,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+   pub stmts: Vec<Stmt>,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+   pub name: String,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+   pub kind: StmtKind,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+   Fn(FnDef),
+   For {
+      pattern: Pattern,
+      iterable: Expr,
+      body: Block,
+   },
+   Loop(Block),
+   Break,
+   Ret(Option<Expr>),
+   /// Assignment to a pattern, which has to be a plain identifier for the
+   /// compound operators.
+   Assign {
+      target: Pattern,
+      op: AssignOp,
+      value: Expr,
+   },
+   Expr(Expr),
+   /// Statement that failed to parse.
+   Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnDef {
+   pub name: Ident,
+   pub params: Vec<Ident>,
+   pub body: Block,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssignOp {
+   Assign,
+   Add,
+   Subtract,
+   Multiply,
+   Divide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+   Equal,
+   Unequal,
+   Less,
+   Greater,
+   LessEqual,
+   GreaterEqual,
+   Add,
+   Subtract,
+   Multiply,
+   Divide,
+   Power,
+   Range,
+   And,
+   Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+   pub kind: ExprKind,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+   Ident(String),
+   /// Source text of the number, such as `-2`, `.5` or `3.`.
+   Number(String),
+   /// Symbol name without the leading `^`.
+   Symbol(String),
+   /// Text between the apostrophes.
+   String(String),
+   Boolean(bool),
+   Call {
+      name: Ident,
+      args: Vec<Expr>,
+   },
+   Parens(Box<Expr>),
+   List(Vec<Expr>),
+   Map(Vec<MapEntry>),
+   Not(Box<Expr>),
+   /// Prefix `-`, on anything but a number literal, which holds its sign.
+   Neg(Box<Expr>),
+   /// Prefix `+`, on anything but a number literal.
+   Pos(Box<Expr>),
+   Binary {
+      op: BinaryOp,
+      left: Box<Expr>,
+      right: Box<Expr>,
+   },
+   If {
+      condition: Box<Expr>,
+      then: Block,
+      /// `ef` branches, tried in turn when the condition does not hold.
+      efs: Vec<ElseIf>,
+      el: Option<Block>,
+   },
+   Match {
+      subject: Box<Expr>,
+      arms: Vec<MatchArm>,
+   },
+   /// Anonymous function, either `|x y| x + y` or with the parameters
+   /// ending the line and the body as the indented block below.
+   Lambda {
+      params: Vec<Ident>,
+      body: Box<Expr>,
+      /// Names of the enclosing scopes the body refers to, see
+      /// `scope::captures`.
+      captures: Vec<String>,
+   },
+   /// Indented block used as a value, as in a match arm or a map entry.
+   Block(Block),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElseIf {
+   pub condition: Expr,
+   pub then: Block,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapEntry {
+   pub key: Expr,
+   pub value: Expr,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+   pub pattern: Pattern,
+   pub body: Expr,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+   pub kind: PatternKind,
+   pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+   Wildcard,
+   Ident(String),
+   /// Number, symbol, string or boolean matching an equal value.
+   Value(Expr),
+   /// Values between two literals, as in `0..9`.
+   Range {
+      start: Expr,
+      end: Expr,
+   },
+   /// Bracketed patterns matching a list element by element, as in
+   /// `[first _ 'x']` or `(key value)`.
+   List(Vec<Pattern>),
+   /// Patterns for the values at literal keys, as in `{ ^ok: value }`.
+   Map(Vec<PatternEntry>),
+   /// Space separated patterns matching multiple values, as in
+   /// `token span back`.
+   Multi(Vec<Pattern>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternEntry {
+   pub key: Expr,
+   pub value: Pattern,
+   pub span: Span,
+}
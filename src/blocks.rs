@@ -0,0 +1,226 @@
+use indentation::blank_line;
+use tokenize::{Tok, TokMeta};
+
+/// Block structure of a single line. Lines are referred to by their index
+/// in `line_starts`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockLine {
+   /// Number of enclosing blocks.
+   pub depth: usize,
+   /// Header line of the innermost enclosing block.
+   pub parent: Option<usize>,
+   /// Last line of the statement, including its continuation lines and the
+   /// block it heads.
+   pub end: usize,
+   /// Double indented line continuing the statement above.
+   pub continuation: bool,
+   /// Empty or comment-only line, placed in the block of the next line.
+   pub blank: bool,
+}
+
+/// Block tree derived from indentation alone, so it is available for
+/// sources that do not parse.
+pub struct BlockTree {
+   lines: Vec<BlockLine>,
+}
+
+impl BlockTree {
+   pub fn new(
+      toks: &[Tok],
+      toks_meta: &[TokMeta],
+      line_starts: &[usize],
+      indentation: usize,
+   ) -> Self {
+      let mut lines: Vec<BlockLine> = Vec::with_capacity(line_starts.len());
+
+      // Spaces and header line of every open block.
+      let mut headers: Vec<(usize, usize)> = vec![];
+      // Spaces and first line of the previous statement.
+      let mut statement: Option<(usize, usize)> = None;
+      let mut last = 0;
+
+      for (line, i) in line_starts.iter().enumerate() {
+         lines.push(BlockLine {
+            depth: 0,
+            parent: None,
+            end: line,
+            continuation: false,
+            blank: blank_line(toks, *i),
+         });
+
+         if lines[line].blank {
+            continue;
+         }
+
+         let spaces = if toks[*i] == Tok::Space {
+            toks_meta[*i].span
+         } else {
+            0
+         };
+
+         let mut continuation = false;
+
+         if let Some((statement_spaces, statement_line)) = statement {
+            if indentation != 0 && spaces == statement_spaces + 2 * indentation {
+               continuation = true;
+               lines[statement_line].end = line;
+            } else if spaces > statement_spaces {
+               headers.push((statement_spaces, statement_line));
+            } else {
+               while headers.last().is_some_and(|h| h.0 >= spaces) {
+                  let (_, header) = headers.pop().unwrap();
+                  lines[header].end = last;
+               }
+            }
+         }
+
+         let current = &mut lines[line];
+         current.depth = headers.len();
+         current.parent = headers.last().map(|h| h.1);
+         current.continuation = continuation;
+
+         if !continuation {
+            statement = Some((spaces, line));
+         }
+
+         last = line;
+      }
+
+      for (_, header) in headers {
+         lines[header].end = last;
+      }
+
+      let mut next: Option<(usize, Option<usize>)> = None;
+      for line in lines.iter_mut().rev() {
+         if line.blank {
+            if let Some((depth, parent)) = next {
+               line.depth = depth;
+               line.parent = parent;
+            }
+         } else {
+            next = Some((line.depth, line.parent));
+         }
+      }
+
+      BlockTree { lines }
+   }
+
+   pub fn lines(&self) -> &[BlockLine] {
+      &self.lines
+   }
+
+   pub fn line(&self, line: usize) -> &BlockLine {
+      &self.lines[line]
+   }
+
+   pub fn depth(&self, line: usize) -> usize {
+      self.lines[line].depth
+   }
+
+   /// Header line of the block enclosing `line`.
+   pub fn enclosing(&self, line: usize) -> Option<usize> {
+      self.lines[line].parent
+   }
+
+   pub fn end(&self, line: usize) -> usize {
+      self.lines[line].end
+   }
+
+   /// Whether `line` starts a statement heading an indented block.
+   pub fn is_header(&self, line: usize) -> bool {
+      self.children(line).next().is_some()
+   }
+
+   /// First lines of the statements directly inside the block headed by
+   /// `line`.
+   pub fn children<'a>(&'a self, line: usize) -> impl Iterator<Item = usize> + 'a {
+      let end = self.lines[line].end;
+      (line + 1..end + 1).filter(move |child| {
+         let child_line = &self.lines[*child];
+         child_line.parent == Some(line) && !child_line.blank && !child_line.continuation
+      })
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   use tokenize::tokenize;
+
+   fn tree(source: &str) -> BlockTree {
+      let chars: Vec<_> = source.chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      BlockTree::new(&toks, &toks_meta, &line_starts, 3)
+   }
+
+   fn shape(tree: &BlockTree) -> Vec<(usize, Option<usize>, usize)> {
+      tree
+         .lines()
+         .iter()
+         .map(|line| (line.depth, line.parent, line.end))
+         .collect()
+   }
+
+   #[test]
+   fn test_blocks() {
+      let tree = tree(indoc!(
+         "
+         fn f(x)
+            if x == 10
+                  and y == 20
+               10
+
+            el
+               # comment
+               40
+         x
+         "
+      ));
+      assert_eq!(
+         shape(&tree),
+         vec![
+            (0, None, 7),
+            (1, Some(0), 3),
+            (1, Some(0), 2),
+            (2, Some(1), 3),
+            (1, Some(0), 4),
+            (1, Some(0), 7),
+            (2, Some(5), 6),
+            (2, Some(5), 7),
+            (0, None, 8),
+         ]
+      );
+      assert!(tree.line(2).continuation);
+      assert!(tree.line(4).blank);
+      assert!(tree.line(6).blank);
+      assert_eq!(tree.children(0).collect::<Vec<_>>(), vec![1, 5]);
+      assert_eq!(tree.children(5).collect::<Vec<_>>(), vec![7]);
+      assert!(tree.is_header(1));
+      assert!(!tree.is_header(3));
+      assert_eq!(tree.enclosing(3), Some(1));
+   }
+
+   #[test]
+   fn test_blocks_unparsable() {
+      let tree = tree(indoc!(
+         "
+         x = (
+           a
+                b
+          c
+         ) )
+         "
+      ));
+      assert_eq!(
+         shape(&tree),
+         vec![
+            (0, None, 3),
+            (1, Some(0), 2),
+            (2, Some(1), 2),
+            (1, Some(0), 3),
+            (0, None, 4),
+         ]
+      );
+   }
+}
@@ -0,0 +1,1057 @@
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use ast;
+use ast::{ExprKind, PatternKind, Span, StmtKind};
+use parse::{Parser, SyntaxError};
+use tokenize::{tokenize, Tok, TokMeta};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntaxKind {
+   Module,
+   Block,
+   /// Name of a function or parameter being defined or of a called function,
+   /// as opposed to `Name` used as a value.
+   Ident,
+
+   Fn,
+   For,
+   Loop,
+   Break,
+   Ret,
+   Assign,
+   ExprStmt,
+   Error,
+
+   Name,
+   Number,
+   Symbol,
+   String,
+   Boolean,
+   Call,
+   Parens,
+   List,
+   Map,
+   MapEntry,
+   Not,
+   Neg,
+   Pos,
+   Binary,
+   If,
+   ElseIf,
+   Match,
+   MatchArm,
+   Lambda,
+   BlockExpr,
+
+   WildcardPattern,
+   IdentPattern,
+   ValuePattern,
+   RangePattern,
+   ListPattern,
+   MapPattern,
+   PatternEntry,
+   MultiPattern,
+}
+
+/// Immutable node without position, sharable between trees. Its tokens
+/// and nodes, in source order, add up to exactly its text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GreenNode {
+   kind: SyntaxKind,
+   len: usize,
+   children: Vec<GreenElement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GreenToken {
+   tok: Tok,
+   text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GreenElement {
+   Node(Rc<GreenNode>),
+   Token(Rc<GreenToken>),
+}
+
+impl GreenNode {
+   pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+      let len = children.iter().map(GreenElement::len).sum();
+      GreenNode {
+         kind,
+         len,
+         children,
+      }
+   }
+
+   pub fn kind(&self) -> SyntaxKind {
+      self.kind
+   }
+
+   /// Length of the text in chars.
+   pub fn len(&self) -> usize {
+      self.len
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.len == 0
+   }
+
+   pub fn children(&self) -> &[GreenElement] {
+      &self.children
+   }
+
+   pub fn text(&self) -> String {
+      let mut text = String::new();
+      self.write_text(&mut text);
+      text
+   }
+
+   fn write_text(&self, text: &mut String) {
+      for child in &self.children {
+         match *child {
+            GreenElement::Node(ref node) => node.write_text(text),
+            GreenElement::Token(ref token) => text.push_str(&token.text),
+         }
+      }
+   }
+}
+
+impl GreenToken {
+   pub fn new(tok: Tok, text: String) -> Self {
+      GreenToken { tok, text }
+   }
+
+   pub fn tok(&self) -> &Tok {
+      &self.tok
+   }
+
+   pub fn text(&self) -> &str {
+      &self.text
+   }
+}
+
+impl GreenElement {
+   pub fn len(&self) -> usize {
+      match *self {
+         GreenElement::Node(ref node) => node.len,
+         GreenElement::Token(ref token) => token.text.chars().count(),
+      }
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.len() == 0
+   }
+}
+
+/// Green node positioned within a tree, knowing its parent and the char
+/// offset it starts at. Cheap to clone, created on the fly while
+/// navigating.
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+struct NodeData {
+   green: Rc<GreenNode>,
+   parent: Option<SyntaxNode>,
+   offset: usize,
+}
+
+#[derive(Clone)]
+pub struct SyntaxToken {
+   green: Rc<GreenToken>,
+   parent: SyntaxNode,
+   offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+   Node(SyntaxNode),
+   Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+   pub fn new_root(green: Rc<GreenNode>) -> Self {
+      SyntaxNode(Rc::new(NodeData {
+         green,
+         parent: None,
+         offset: 0,
+      }))
+   }
+
+   pub fn kind(&self) -> SyntaxKind {
+      self.0.green.kind
+   }
+
+   pub fn green(&self) -> &Rc<GreenNode> {
+      &self.0.green
+   }
+
+   pub fn parent(&self) -> Option<SyntaxNode> {
+      self.0.parent.clone()
+   }
+
+   /// Chars covered within the source of the whole tree.
+   pub fn range(&self) -> Range<usize> {
+      self.0.offset..self.0.offset + self.0.green.len
+   }
+
+   pub fn text(&self) -> String {
+      self.0.green.text()
+   }
+
+   pub fn children_with_tokens(&self) -> Vec<SyntaxElement> {
+      let mut offset = self.0.offset;
+
+      self
+         .0
+         .green
+         .children
+         .iter()
+         .map(|child| {
+            let element = match *child {
+               GreenElement::Node(ref green) => {
+                  SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                     green: green.clone(),
+                     parent: Some(self.clone()),
+                     offset,
+                  })))
+               }
+               GreenElement::Token(ref green) => SyntaxElement::Token(SyntaxToken {
+                  green: green.clone(),
+                  parent: self.clone(),
+                  offset,
+               }),
+            };
+            offset += child.len();
+            element
+         })
+         .collect()
+   }
+
+   pub fn children(&self) -> Vec<SyntaxNode> {
+      self
+         .children_with_tokens()
+         .into_iter()
+         .filter_map(|element| match element {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+         })
+         .collect()
+   }
+
+   /// Tokens directly within the node, not within its child nodes.
+   pub fn tokens(&self) -> Vec<SyntaxToken> {
+      self
+         .children_with_tokens()
+         .into_iter()
+         .filter_map(|element| match element {
+            SyntaxElement::Token(token) => Some(token),
+            SyntaxElement::Node(_) => None,
+         })
+         .collect()
+   }
+
+   /// Every token within the node, in source order.
+   pub fn descendant_tokens(&self) -> Vec<SyntaxToken> {
+      let mut tokens = Vec::new();
+      for element in self.children_with_tokens() {
+         match element {
+            SyntaxElement::Node(node) => tokens.extend(node.descendant_tokens()),
+            SyntaxElement::Token(token) => tokens.push(token),
+         }
+      }
+      tokens
+   }
+}
+
+impl PartialEq for SyntaxNode {
+   fn eq(&self, other: &Self) -> bool {
+      Rc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+   }
+}
+
+impl fmt::Debug for SyntaxNode {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      let range = self.range();
+      write!(f, "{:?}@{}..{}", self.kind(), range.start, range.end)
+   }
+}
+
+impl SyntaxToken {
+   pub fn tok(&self) -> &Tok {
+      &self.green.tok
+   }
+
+   pub fn text(&self) -> &str {
+      &self.green.text
+   }
+
+   pub fn parent(&self) -> &SyntaxNode {
+      &self.parent
+   }
+
+   pub fn range(&self) -> Range<usize> {
+      self.offset..self.offset + self.green.text.chars().count()
+   }
+}
+
+impl PartialEq for SyntaxToken {
+   fn eq(&self, other: &Self) -> bool {
+      Rc::ptr_eq(&self.green, &other.green) && self.offset == other.offset
+   }
+}
+
+impl fmt::Debug for SyntaxToken {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      let range = self.range();
+      write!(
+         f,
+         "{:?}@{}..{} {:?}",
+         self.tok(),
+         range.start,
+         range.end,
+         self.text()
+      )
+   }
+}
+
+/// Lossless tree of `chars`, with the errors of the statements parsed as
+/// `SyntaxKind::Error` nodes.
+pub fn parse(chars: &[char]) -> (SyntaxNode, Vec<SyntaxError>) {
+   let (toks, toks_meta, line_starts) = tokenize(chars);
+   let parsed = Parser::new(chars, &toks, &toks_meta, &line_starts).parse();
+   let root = build(chars, &toks, &toks_meta, &parsed.module);
+   (root, parsed.errors)
+}
+
+/// Tree with a node for every node of `module` and every token hanging off
+/// the innermost node covering it, so that its text is exactly `chars`.
+pub fn build(
+   chars: &[char],
+   toks: &[Tok],
+   toks_meta: &[TokMeta],
+   module: &ast::Module,
+) -> SyntaxNode {
+   let builder = Builder {
+      chars,
+      toks,
+      toks_meta,
+   };
+   let stmts = module.body.iter().map(|stmt| builder.stmt(stmt)).collect();
+   let (_, green) = builder.node(SyntaxKind::Module, Span::new(0, toks.len()), stmts);
+   SyntaxNode::new_root(green)
+}
+
+/// Node built for an AST node, together with the tokens it covers.
+type Built = (Span, Rc<GreenNode>);
+
+struct Builder<'a> {
+   chars: &'a [char],
+   toks: &'a [Tok],
+   toks_meta: &'a [TokMeta],
+}
+
+impl<'a> Builder<'a> {
+   /// Node covering `span` and the spans of `children`, with the tokens
+   /// not covered by any child placed between them.
+   fn node(&self, kind: SyntaxKind, span: Span, children: Vec<Built>) -> Built {
+      let span = children.iter().fold(span, |span, &(child, _)| {
+         Span::new(span.start.min(child.start), span.end.max(child.end))
+      });
+
+      let mut elements = Vec::new();
+      let mut pos = span.start;
+      for (child, green) in children {
+         debug_assert!(child.start >= pos);
+         self.tokens(pos, child.start, &mut elements);
+         elements.push(GreenElement::Node(green));
+         pos = child.end;
+      }
+      self.tokens(pos, span.end, &mut elements);
+
+      (span, Rc::new(GreenNode::new(kind, elements)))
+   }
+
+   fn tokens(&self, start: usize, end: usize, elements: &mut Vec<GreenElement>) {
+      for pos in start..end {
+         let meta = &self.toks_meta[pos];
+         let text = self.chars[meta.end - meta.span..meta.end].iter().collect();
+         elements.push(GreenElement::Token(Rc::new(GreenToken::new(
+            self.toks[pos].clone(),
+            text,
+         ))));
+      }
+   }
+
+   fn block(&self, block: &ast::Block) -> Built {
+      let stmts = block.stmts.iter().map(|stmt| self.stmt(stmt)).collect();
+      self.node(SyntaxKind::Block, block.span, stmts)
+   }
+
+   fn ident(&self, ident: &ast::Ident) -> Built {
+      self.node(SyntaxKind::Ident, ident.span, Vec::new())
+   }
+
+   fn stmt(&self, stmt: &ast::Stmt) -> Built {
+      let (kind, children) = match stmt.kind {
+         StmtKind::Fn(ref def) => {
+            let mut children = vec![self.ident(&def.name)];
+            children.extend(def.params.iter().map(|param| self.ident(param)));
+            children.push(self.block(&def.body));
+            (SyntaxKind::Fn, children)
+         }
+         StmtKind::For {
+            ref pattern,
+            ref iterable,
+            ref body,
+         } => (
+            SyntaxKind::For,
+            vec![self.pattern(pattern), self.expr(iterable), self.block(body)],
+         ),
+         StmtKind::Loop(ref body) => (SyntaxKind::Loop, vec![self.block(body)]),
+         StmtKind::Break => (SyntaxKind::Break, Vec::new()),
+         StmtKind::Ret(ref value) => (
+            SyntaxKind::Ret,
+            value.iter().map(|value| self.expr(value)).collect(),
+         ),
+         StmtKind::Assign {
+            ref target,
+            ref value,
+            ..
+         } => (
+            SyntaxKind::Assign,
+            vec![self.pattern(target), self.expr(value)],
+         ),
+         StmtKind::Expr(ref expr) => (SyntaxKind::ExprStmt, vec![self.expr(expr)]),
+         StmtKind::Error => (SyntaxKind::Error, Vec::new()),
+      };
+
+      self.node(kind, stmt.span, children)
+   }
+
+   fn expr(&self, expr: &ast::Expr) -> Built {
+      let (kind, children) = match expr.kind {
+         ExprKind::Ident(_) => (SyntaxKind::Name, Vec::new()),
+         ExprKind::Number(_) => (SyntaxKind::Number, Vec::new()),
+         ExprKind::Symbol(_) => (SyntaxKind::Symbol, Vec::new()),
+         ExprKind::String(_) => (SyntaxKind::String, Vec::new()),
+         ExprKind::Boolean(_) => (SyntaxKind::Boolean, Vec::new()),
+         ExprKind::Call { ref name, ref args } => {
+            let mut children = vec![self.ident(name)];
+            children.extend(args.iter().map(|arg| self.expr(arg)));
+            (SyntaxKind::Call, children)
+         }
+         ExprKind::Parens(ref inner) => (SyntaxKind::Parens, vec![self.expr(inner)]),
+         ExprKind::List(ref items) => (
+            SyntaxKind::List,
+            items.iter().map(|item| self.expr(item)).collect(),
+         ),
+         ExprKind::Map(ref entries) => (
+            SyntaxKind::Map,
+            entries
+               .iter()
+               .map(|entry| {
+                  self.node(
+                     SyntaxKind::MapEntry,
+                     entry.span,
+                     vec![self.expr(&entry.key), self.expr(&entry.value)],
+                  )
+               })
+               .collect(),
+         ),
+         ExprKind::Not(ref operand) => (SyntaxKind::Not, vec![self.expr(operand)]),
+         ExprKind::Neg(ref operand) => (SyntaxKind::Neg, vec![self.expr(operand)]),
+         ExprKind::Pos(ref operand) => (SyntaxKind::Pos, vec![self.expr(operand)]),
+         ExprKind::Binary {
+            ref left,
+            ref right,
+            ..
+         } => (SyntaxKind::Binary, vec![self.expr(left), self.expr(right)]),
+         ExprKind::If {
+            ref condition,
+            ref then,
+            ref efs,
+            ref el,
+         } => {
+            let mut children = vec![self.expr(condition), self.block(then)];
+            children.extend(efs.iter().map(|ef| {
+               self.node(
+                  SyntaxKind::ElseIf,
+                  ef.span,
+                  vec![self.expr(&ef.condition), self.block(&ef.then)],
+               )
+            }));
+            children.extend(el.iter().map(|el| self.block(el)));
+            (SyntaxKind::If, children)
+         }
+         ExprKind::Match {
+            ref subject,
+            ref arms,
+         } => {
+            let mut children = vec![self.expr(subject)];
+            children.extend(arms.iter().map(|arm| {
+               self.node(
+                  SyntaxKind::MatchArm,
+                  arm.span,
+                  vec![self.pattern(&arm.pattern), self.expr(&arm.body)],
+               )
+            }));
+            (SyntaxKind::Match, children)
+         }
+         ExprKind::Lambda {
+            ref params,
+            ref body,
+            ..
+         } => {
+            let mut children: Vec<_> = params.iter().map(|param| self.ident(param)).collect();
+            children.push(self.expr(body));
+            (SyntaxKind::Lambda, children)
+         }
+         ExprKind::Block(ref block) => (SyntaxKind::BlockExpr, vec![self.block(block)]),
+      };
+
+      self.node(kind, expr.span, children)
+   }
+
+   fn pattern(&self, pattern: &ast::Pattern) -> Built {
+      let (kind, children) = match pattern.kind {
+         PatternKind::Wildcard => (SyntaxKind::WildcardPattern, Vec::new()),
+         PatternKind::Ident(_) => (SyntaxKind::IdentPattern, Vec::new()),
+         PatternKind::Value(ref value) => (SyntaxKind::ValuePattern, vec![self.expr(value)]),
+         PatternKind::Range { ref start, ref end } => (
+            SyntaxKind::RangePattern,
+            vec![self.expr(start), self.expr(end)],
+         ),
+         PatternKind::List(ref patterns) => (
+            SyntaxKind::ListPattern,
+            patterns
+               .iter()
+               .map(|pattern| self.pattern(pattern))
+               .collect(),
+         ),
+         PatternKind::Map(ref entries) => (
+            SyntaxKind::MapPattern,
+            entries
+               .iter()
+               .map(|entry| {
+                  self.node(
+                     SyntaxKind::PatternEntry,
+                     entry.span,
+                     vec![self.expr(&entry.key), self.pattern(&entry.value)],
+                  )
+               })
+               .collect(),
+         ),
+         PatternKind::Multi(ref patterns) => (
+            SyntaxKind::MultiPattern,
+            patterns
+               .iter()
+               .map(|pattern| self.pattern(pattern))
+               .collect(),
+         ),
+      };
+
+      self.node(kind, pattern.span, children)
+   }
+}
+
+/// Typed view of the nodes of a given kind.
+pub trait AstNode: Sized {
+   fn cast(node: SyntaxNode) -> Option<Self>;
+
+   fn syntax(&self) -> &SyntaxNode;
+}
+
+macro_rules! ast_node {
+   ($name:ident, $($kind:ident)|+) => {
+      #[derive(Debug, Clone, PartialEq)]
+      pub struct $name(SyntaxNode);
+
+      impl AstNode for $name {
+         fn cast(node: SyntaxNode) -> Option<Self> {
+            match node.kind() {
+               $(SyntaxKind::$kind)|+ => Some($name(node)),
+               _ => None,
+            }
+         }
+
+         fn syntax(&self) -> &SyntaxNode {
+            &self.0
+         }
+      }
+   };
+}
+
+ast_node!(Module, Module);
+ast_node!(Block, Block);
+ast_node!(Ident, Ident);
+ast_node!(
+   Stmt,
+   Fn | For | Loop | Break | Ret | Assign | ExprStmt | Error
+);
+ast_node!(FnDef, Fn);
+ast_node!(For, For);
+ast_node!(Assign, Assign);
+ast_node!(
+   Expr,
+   Name
+      | Number
+      | Symbol
+      | String
+      | Boolean
+      | Call
+      | Parens
+      | List
+      | Map
+      | Not
+      | Neg
+      | Pos
+      | Binary
+      | If
+      | Match
+      | Lambda
+      | BlockExpr
+);
+ast_node!(Call, Call);
+ast_node!(Binary, Binary);
+ast_node!(If, If);
+ast_node!(ElseIf, ElseIf);
+ast_node!(Match, Match);
+ast_node!(MatchArm, MatchArm);
+ast_node!(Lambda, Lambda);
+ast_node!(MapEntry, MapEntry);
+ast_node!(
+   Pattern,
+   WildcardPattern
+      | IdentPattern
+      | ValuePattern
+      | RangePattern
+      | ListPattern
+      | MapPattern
+      | MultiPattern
+);
+ast_node!(PatternEntry, PatternEntry);
+
+fn children<T: AstNode>(node: &SyntaxNode) -> Vec<T> {
+   node.children().into_iter().filter_map(T::cast).collect()
+}
+
+fn child<T: AstNode>(node: &SyntaxNode) -> Option<T> {
+   node.children().into_iter().filter_map(T::cast).next()
+}
+
+/// First token directly within the node that is not layout, such as the
+/// operator of a binary expression.
+fn operator(node: &SyntaxNode) -> Option<SyntaxToken> {
+   node.tokens().into_iter().find(|token| {
+      !token.tok().is_trivia() && *token.tok() != Tok::LineEnd && *token.tok() != Tok::Indent
+   })
+}
+
+impl Module {
+   pub fn stmts(&self) -> Vec<Stmt> {
+      children(&self.0)
+   }
+}
+
+impl Block {
+   pub fn stmts(&self) -> Vec<Stmt> {
+      children(&self.0)
+   }
+}
+
+impl Ident {
+   pub fn name(&self) -> String {
+      self.0.text().trim().to_string()
+   }
+}
+
+impl Stmt {
+   pub fn kind(&self) -> SyntaxKind {
+      self.0.kind()
+   }
+}
+
+impl FnDef {
+   pub fn name(&self) -> Option<Ident> {
+      child(&self.0)
+   }
+
+   pub fn params(&self) -> Vec<Ident> {
+      children(&self.0).into_iter().skip(1).collect()
+   }
+
+   pub fn body(&self) -> Option<Block> {
+      child(&self.0)
+   }
+}
+
+impl For {
+   pub fn pattern(&self) -> Option<Pattern> {
+      child(&self.0)
+   }
+
+   pub fn iterable(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+
+   pub fn body(&self) -> Option<Block> {
+      child(&self.0)
+   }
+}
+
+impl Assign {
+   pub fn target(&self) -> Option<Pattern> {
+      child(&self.0)
+   }
+
+   /// `=` or one of the compound assignment operators.
+   pub fn op(&self) -> Option<SyntaxToken> {
+      operator(&self.0)
+   }
+
+   pub fn value(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+}
+
+impl Expr {
+   pub fn kind(&self) -> SyntaxKind {
+      self.0.kind()
+   }
+}
+
+impl Call {
+   pub fn name(&self) -> Option<Ident> {
+      child(&self.0)
+   }
+
+   pub fn args(&self) -> Vec<Expr> {
+      children(&self.0)
+   }
+}
+
+impl Binary {
+   pub fn left(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+
+   pub fn op(&self) -> Option<SyntaxToken> {
+      operator(&self.0)
+   }
+
+   pub fn right(&self) -> Option<Expr> {
+      children(&self.0).into_iter().nth(1)
+   }
+}
+
+impl If {
+   pub fn condition(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+
+   pub fn then(&self) -> Option<Block> {
+      child(&self.0)
+   }
+
+   pub fn efs(&self) -> Vec<ElseIf> {
+      children(&self.0)
+   }
+
+   pub fn el(&self) -> Option<Block> {
+      children(&self.0).into_iter().nth(1)
+   }
+}
+
+impl ElseIf {
+   pub fn condition(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+
+   pub fn then(&self) -> Option<Block> {
+      child(&self.0)
+   }
+}
+
+impl Match {
+   pub fn subject(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+
+   pub fn arms(&self) -> Vec<MatchArm> {
+      children(&self.0)
+   }
+}
+
+impl MatchArm {
+   pub fn pattern(&self) -> Option<Pattern> {
+      child(&self.0)
+   }
+
+   pub fn body(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+}
+
+impl Lambda {
+   pub fn params(&self) -> Vec<Ident> {
+      children(&self.0)
+   }
+
+   /// Expression after the parameters, or a `BlockExpr` for the indented
+   /// form.
+   pub fn body(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+}
+
+impl MapEntry {
+   pub fn key(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+
+   pub fn value(&self) -> Option<Expr> {
+      children(&self.0).into_iter().nth(1)
+   }
+}
+
+impl Pattern {
+   pub fn kind(&self) -> SyntaxKind {
+      self.0.kind()
+   }
+
+   /// Name bound by an identifier pattern.
+   pub fn name(&self) -> Option<String> {
+      if self.kind() != SyntaxKind::IdentPattern {
+         return None;
+      }
+
+      self
+         .0
+         .tokens()
+         .into_iter()
+         .find(|token| *token.tok() == Tok::Identifier)
+         .map(|token| token.text().to_string())
+   }
+
+   /// Nested patterns of a list or multi-value pattern.
+   pub fn patterns(&self) -> Vec<Pattern> {
+      children(&self.0)
+   }
+
+   pub fn entries(&self) -> Vec<PatternEntry> {
+      children(&self.0)
+   }
+}
+
+impl PatternEntry {
+   pub fn key(&self) -> Option<Expr> {
+      child(&self.0)
+   }
+
+   pub fn value(&self) -> Option<Pattern> {
+      child(&self.0)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn parse_str(source: &str) -> (SyntaxNode, Vec<SyntaxError>) {
+      let chars: Vec<_> = source.chars().collect();
+      parse(&chars)
+   }
+
+   /// Nodes one per line, indented by depth, with the tokens directly within
+   /// them, ignoring layout.
+   fn dump(node: &SyntaxNode, depth: usize, out: &mut String) {
+      out.push_str(&"  ".repeat(depth));
+      out.push_str(&format!("{:?}", node.kind()));
+      for element in node.children_with_tokens() {
+         if let SyntaxElement::Token(ref token) = element {
+            if !token.tok().is_trivia() && *token.tok() != Tok::LineEnd {
+               out.push(' ');
+               out.push_str(token.text());
+            }
+         }
+      }
+      out.push('\n');
+
+      for child in node.children() {
+         dump(&child, depth + 1, out);
+      }
+   }
+
+   fn tree(source: &str) -> String {
+      let mut out = String::new();
+      dump(&parse_str(source).0, 0, &mut out);
+      out
+   }
+
+   #[test]
+   fn test_lossless() {
+      for source in &[
+         "",
+         "\n\n",
+         "x = 1  # One.\n",
+         "  \n# Comment\r\nfn f(a b)\r\n   ret a + b\r\n\r\n",
+         "fn f(x\n   x\ny = (1 +\nz = 1\n",
+         "m = { 1 2 }\n",
+         include_str!("../lax/tokenize.lax"),
+         include_str!("../lax/block.lax"),
+      ] {
+         let (root, _) = parse_str(source);
+         assert_eq!(root.text(), *source);
+         assert_eq!(root.range(), 0..source.chars().count());
+
+         let tokens: String = root
+            .descendant_tokens()
+            .iter()
+            .map(|token| token.text().to_string())
+            .collect();
+         assert_eq!(tokens, *source);
+      }
+   }
+
+   #[test]
+   fn test_tree() {
+      assert_eq!(
+         tree("fn f(a)\n   # Sum.\n   ret a + 1\n"),
+         indoc!(
+            "
+            Module
+              Fn fn ( )
+                Ident f
+                Ident a
+                Block
+                  Ret ret
+                    Binary +
+                      Name a
+                      Number 1
+            "
+         )
+      );
+      assert_eq!(
+         tree("x = 1\n)\ny = 2\n"),
+         indoc!(
+            "
+            Module
+              Assign =
+                IdentPattern x
+                Number 1
+              Error )
+              Assign =
+                IdentPattern y
+                Number 2
+            "
+         )
+      );
+   }
+
+   #[test]
+   fn test_navigation() {
+      let (root, _) = parse_str("a = 1\nb = a +  2\n");
+      let stmts = root.children();
+      let binary = stmts[1].children()[1].clone();
+      assert_eq!(binary.kind(), SyntaxKind::Binary);
+      assert_eq!(binary.range(), 10..16);
+      assert_eq!(binary.text(), "a +  2");
+      assert_eq!(binary.parent(), Some(stmts[1].clone()));
+      assert_eq!(binary.parent().unwrap().parent(), Some(root));
+
+      let token = &binary.tokens()[1];
+      assert_eq!(*token.tok(), Tok::Plus);
+      assert_eq!(token.range(), 12..13);
+      assert_eq!(token.parent(), &binary);
+   }
+
+   #[test]
+   fn test_typed() {
+      let (root, _) = parse_str(indoc!(
+         "
+         fn add(a b)
+            ret a + b
+         total += add(1 2)
+         "
+      ));
+      let module = Module::cast(root.clone()).unwrap();
+      assert_eq!(Block::cast(root), None);
+
+      let stmts = module.stmts();
+      assert_eq!(stmts.len(), 2);
+
+      let def = FnDef::cast(stmts[0].syntax().clone()).unwrap();
+      assert_eq!(def.name().unwrap().name(), "add");
+      let params: Vec<_> = def.params().iter().map(Ident::name).collect();
+      assert_eq!(params, vec!["a", "b"]);
+
+      let ret = &def.body().unwrap().stmts()[0];
+      assert_eq!(ret.kind(), SyntaxKind::Ret);
+      let sum = child::<Binary>(ret.syntax()).unwrap();
+      assert_eq!(sum.left().unwrap().syntax().text(), "a");
+      assert_eq!(sum.op().unwrap().text(), "+");
+      assert_eq!(sum.right().unwrap().syntax().text(), "b");
+
+      let assign = Assign::cast(stmts[1].syntax().clone()).unwrap();
+      assert_eq!(assign.target().unwrap().name().unwrap(), "total");
+      assert_eq!(*assign.op().unwrap().tok(), Tok::PlusEquals);
+      let call = Call::cast(assign.value().unwrap().syntax().clone()).unwrap();
+      assert_eq!(call.name().unwrap().name(), "add");
+      assert_eq!(call.args().len(), 2);
+   }
+
+   #[test]
+   fn test_patterns() {
+      let (root, errors) = parse_str("for [k { ^v: v }] _ in xs\n   k\n");
+      assert_eq!(errors, vec![]);
+
+      let pattern = For::cast(root.children()[0].clone())
+         .unwrap()
+         .pattern()
+         .unwrap();
+      assert_eq!(pattern.kind(), SyntaxKind::MultiPattern);
+
+      let patterns = pattern.patterns();
+      let kinds: Vec<_> = patterns.iter().map(Pattern::kind).collect();
+      assert_eq!(
+         kinds,
+         vec![SyntaxKind::ListPattern, SyntaxKind::WildcardPattern]
+      );
+
+      let list = patterns[0].patterns();
+      assert_eq!(list[0].name().unwrap(), "k");
+      assert_eq!(list[1].name(), None);
+
+      let entry = &list[1].entries()[0];
+      assert_eq!(entry.key().unwrap().kind(), SyntaxKind::Symbol);
+      assert_eq!(entry.value().unwrap().name().unwrap(), "v");
+   }
+
+   #[test]
+   fn test_lambda() {
+      let (root, errors) = parse_str("f = |x y|\n   x + y\n");
+      assert_eq!(errors, vec![]);
+
+      let assign = Assign::cast(root.children()[0].clone()).unwrap();
+      let lambda = Lambda::cast(assign.value().unwrap().syntax().clone()).unwrap();
+      let params: Vec<_> = lambda.params().iter().map(Ident::name).collect();
+      assert_eq!(params, vec!["x", "y"]);
+      assert_eq!(lambda.body().unwrap().kind(), SyntaxKind::BlockExpr);
+   }
+
+   #[test]
+   fn test_ef() {
+      let (root, errors) = parse_str("if a\n   1\nef b\n   2\nel\n   3\n");
+      assert_eq!(errors, vec![]);
+
+      let if_ = If::cast(root.children()[0].children()[0].clone()).unwrap();
+      let efs = if_.efs();
+      assert_eq!(efs.len(), 1);
+      assert_eq!(efs[0].syntax().text(), "ef b\n   2");
+      assert_eq!(efs[0].condition().unwrap().syntax().text(), "b");
+      assert_eq!(if_.el().unwrap().syntax().text(), "3");
+   }
+}
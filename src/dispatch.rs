@@ -0,0 +1,144 @@
+use advancer::Advancer;
+
+/// Longest pattern a `Dispatch` accepts.
+pub const MAX_PATTERN: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum SpecError {
+   Empty,
+   TooLong(&'static str),
+   NonAscii(&'static str),
+   Ambiguous(&'static str),
+   Unreachable(&'static str),
+}
+
+/// First-char indexed table of fixed-spelling tokens.
+///
+/// Built from a declarative spec of `(pattern, token)` pairs in any order.
+/// Patterns sharing a first char are tried longest first, so `**` wins over
+/// `*` regardless of where either is declared.
+pub struct Dispatch<K> {
+   entries: Vec<(Vec<char>, K)>,
+   first: [(usize, usize); 128],
+}
+
+impl<K: Clone> Dispatch<K> {
+   /// `claimed` tells which first chars already belong to other matchers,
+   /// such as identifiers or digits; patterns starting with them could never
+   /// be reached.
+   pub fn new(spec: &[(&'static str, K)], claimed: fn(char) -> bool) -> Result<Self, SpecError> {
+      let mut sorted: Vec<_> = spec.iter().collect();
+      sorted.sort_by(|a, b| {
+         let a_first = a.0.chars().next();
+         let b_first = b.0.chars().next();
+         a_first
+            .cmp(&b_first)
+            .then(b.0.chars().count().cmp(&a.0.chars().count()))
+      });
+
+      let mut entries = Vec::with_capacity(sorted.len());
+      let mut first = [(0, 0); 128];
+
+      for (i, &&(pattern, ref tok)) in sorted.iter().enumerate() {
+         let chars: Vec<char> = pattern.chars().collect();
+
+         let c = match chars.first() {
+            Some(c) => *c,
+            None => return Err(SpecError::Empty),
+         };
+
+         if chars.len() > MAX_PATTERN {
+            return Err(SpecError::TooLong(pattern));
+         }
+
+         if !chars.iter().all(char::is_ascii) {
+            return Err(SpecError::NonAscii(pattern));
+         }
+
+         if claimed(c) {
+            return Err(SpecError::Unreachable(pattern));
+         }
+
+         if sorted[..i].iter().any(|other| other.0 == pattern) {
+            return Err(SpecError::Ambiguous(pattern));
+         }
+
+         let range = &mut first[c as usize];
+         if range.0 == range.1 {
+            *range = (i, i);
+         }
+         range.1 += 1;
+
+         entries.push((chars, tok.clone()));
+      }
+
+      Ok(Dispatch { entries, first })
+   }
+
+   pub fn run(&self, advancer: &mut Advancer<char>) -> Option<(K, usize)> {
+      debug_assert!(!advancer.completed());
+
+      let c = *advancer.peek_nth(0)? as usize;
+      let &(start, end) = self.first.get(c)?;
+
+      'entries: for (pattern, tok) in &self.entries[start..end] {
+         for c in pattern {
+            if advancer.one(*c).is_none() {
+               continue 'entries;
+            }
+         }
+
+         return Some((tok.clone(), advancer.consume()));
+      }
+
+      None
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn claimed(c: char) -> bool {
+      c.is_ascii_alphanumeric()
+   }
+
+   fn run(dispatch: &Dispatch<u8>, input: &str) -> Option<(u8, usize)> {
+      let chars: Vec<_> = input.chars().collect();
+      dispatch.run(&mut Advancer::new(&chars))
+   }
+
+   #[test]
+   fn test_longest_match() {
+      let dispatch = Dispatch::new(
+         &[("*", 1), ("<", 2), ("**=", 3), ("**", 4), ("<=", 5)],
+         claimed,
+      )
+      .unwrap();
+      assert_eq!(run(&dispatch, "*"), Some((1, 1)));
+      assert_eq!(run(&dispatch, "**"), Some((4, 2)));
+      assert_eq!(run(&dispatch, "**="), Some((3, 3)));
+      assert_eq!(run(&dispatch, "***"), Some((4, 2)));
+      assert_eq!(run(&dispatch, "*=*"), Some((1, 1)));
+      assert_eq!(run(&dispatch, "<=="), Some((5, 2)));
+      assert_eq!(run(&dispatch, ">"), None);
+      assert_eq!(run(&dispatch, "Я"), None);
+   }
+
+   #[test]
+   fn test_spec_errors() {
+      let new = |spec: &[(&'static str, u8)]| Dispatch::new(spec, claimed).err();
+      assert_eq!(new(&[("*", 1), ("", 2)]), Some(SpecError::Empty));
+      assert_eq!(new(&[("<<<<<", 1)]), Some(SpecError::TooLong("<<<<<")));
+      assert_eq!(new(&[("*Я", 1)]), Some(SpecError::NonAscii("*Я")));
+      assert_eq!(
+         new(&[("**", 1), ("*", 2), ("**", 3)]),
+         Some(SpecError::Ambiguous("**"))
+      );
+      assert_eq!(
+         new(&[("*", 1), ("a*", 2)]),
+         Some(SpecError::Unreachable("a*"))
+      );
+      assert_eq!(new(&[("*", 1), ("**", 2)]), None);
+   }
+}
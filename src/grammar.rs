@@ -0,0 +1,1091 @@
+use std::fmt::Debug;
+
+use tokenize::{Tok, TokMeta};
+use trace::Trace;
+
+/// Identifier of a grammar element, typically a fieldless enum. The index
+/// locates the element in the table built by `Builder`, so it should be
+/// small and dense.
+pub trait ElementId: Copy + PartialEq + Debug {
+   fn index(self) -> usize;
+}
+
+/// Position of elements that are referenced but never defined.
+const UNDEFINED: usize = usize::MAX;
+
+#[derive(Debug, PartialEq)]
+pub enum Instruction<E> {
+   Element(E),
+   /// Element whose contents are matched one indentation level deeper.
+   Block(E),
+   Reference(E),
+   Tok(Tok),
+   Sequence(usize),
+   Choice(usize),
+   ZeroOrOne(usize),
+   ZeroOrMore(usize),
+   Indentation(usize),
+}
+
+/// Assembles the instructions of a grammar, each element being defined
+/// by chaining its contents after `element` or `block`, with `end` closing
+/// every `sequence`, `choice`, `zero_or_one` and `zero_or_more`.
+pub struct Builder<E> {
+   instructions: Vec<Instruction<E>>,
+   starts: Vec<usize>,
+   elements: Vec<usize>,
+}
+
+impl<E: ElementId> Default for Builder<E> {
+   fn default() -> Self {
+      Self::new()
+   }
+}
+
+impl<E: ElementId> Builder<E> {
+   pub fn new() -> Self {
+      Builder {
+         instructions: Vec::new(),
+         starts: Vec::new(),
+         elements: Vec::new(),
+      }
+   }
+
+   /// Instructions and the position of every element within them, indexed
+   /// by `ElementId::index`.
+   pub fn destructure(self) -> (Vec<Instruction<E>>, Vec<usize>) {
+      debug_assert!(self.starts.is_empty());
+
+      let Self {
+         instructions,
+         elements,
+         ..
+      } = self;
+      (instructions, elements)
+   }
+
+   pub fn element(&mut self, element: E) -> &mut Self {
+      self.define(element);
+      self.instructions.push(Instruction::Element(element));
+      self
+   }
+
+   /// Defines an element whose contents are matched one indentation level
+   /// deeper.
+   pub fn block(&mut self, element: E) -> &mut Self {
+      self.define(element);
+      self.instructions.push(Instruction::Block(element));
+      self
+   }
+
+   fn define(&mut self, element: E) {
+      let index = element.index();
+      if self.elements.len() <= index {
+         self.elements.resize(index + 1, UNDEFINED);
+      }
+      self.elements[index] = self.instructions.len();
+   }
+
+   #[rustfmt::skip]
+   pub fn skip_space(&mut self) -> &mut Self {
+      self
+         .zero_or_one()
+            .choice()
+               .tok(Tok::Space)
+               .sequence()
+                  .tok(Tok::LineEnd)
+                  .indentation(2)
+               .end()
+            .end()
+         .end()
+   }
+
+   pub fn indentation(&mut self, indentation: usize) -> &mut Self {
+      self
+         .instructions
+         .push(Instruction::Indentation(indentation));
+      self
+   }
+
+   pub fn reference(&mut self, element: E) -> &mut Self {
+      self.instructions.push(Instruction::Reference(element));
+      self
+   }
+
+   pub fn tok(&mut self, tok: Tok) -> &mut Self {
+      self.instructions.push(Instruction::Tok(tok));
+      self
+   }
+
+   pub fn sequence(&mut self) -> &mut Self {
+      self.start(Instruction::Sequence(0));
+      self
+   }
+
+   pub fn choice(&mut self) -> &mut Self {
+      self.start(Instruction::Choice(0));
+      self
+   }
+
+   pub fn zero_or_one(&mut self) -> &mut Self {
+      self.start(Instruction::ZeroOrOne(0));
+      self
+   }
+
+   pub fn zero_or_more(&mut self) -> &mut Self {
+      self.start(Instruction::ZeroOrMore(0));
+      self
+   }
+
+   fn start(&mut self, parent: Instruction<E>) -> &mut Self {
+      self.starts.push(self.instructions.len());
+      self.instructions.push(parent);
+      self
+   }
+
+   pub fn end(&mut self) -> &mut Self {
+      debug_assert!(!self.starts.is_empty());
+
+      let start = self.starts.pop().unwrap();
+
+      debug_assert!(self.instructions.len() > start);
+
+      let end = self.instructions.len();
+
+      match *unsafe { self.instructions.get_unchecked_mut(start) } {
+         Instruction::Sequence(ref mut i)
+         | Instruction::Choice(ref mut i)
+         | Instruction::ZeroOrOne(ref mut i)
+         | Instruction::ZeroOrMore(ref mut i) => *i = end,
+         _ => unreachable!(),
+      }
+
+      self
+   }
+}
+
+/// Mistake in a grammar found by `analyze`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning<E> {
+   /// Elements that reach themselves without consuming a token, looping
+   /// forever.
+   LeftRecursion(Vec<E>),
+   /// `zero_or_more` at `pos`, within `element`, whose contents can match
+   /// without consuming a token, looping forever.
+   EmptyRepetition { element: E, pos: usize },
+   /// Element referenced but never defined, panicking when reached.
+   Undefined(E),
+   /// Element defined but not reachable from the root.
+   Unreachable(E),
+   /// Index within the element positions that no element is defined or
+   /// referenced at, making the table larger than needed.
+   UnusedIndex(usize),
+}
+
+/// Checks the instructions assembled by `Builder` before parsing from
+/// `root`, listing left recursion first, then empty repetitions in
+/// instruction order, then undefined, unreachable and unused elements.
+pub fn analyze<E: ElementId>(
+   instructions: &[Instruction<E>],
+   elements: &[usize],
+   root: E,
+) -> Vec<Warning<E>> {
+   let mut warnings: Vec<Warning<E>> = left_recursion(instructions, elements)
+      .into_iter()
+      .map(Warning::LeftRecursion)
+      .collect();
+
+   let nullable = nullable_elements(instructions, elements);
+   let mut current = None;
+   for (pos, instruction) in instructions.iter().enumerate() {
+      match *instruction {
+         Instruction::Element(element) | Instruction::Block(element) => current = Some(element),
+         Instruction::ZeroOrMore(_)
+            if children(instructions, pos)
+               .iter()
+               .all(|&child| nullable_at(instructions, &nullable, child)) =>
+         {
+            warnings.push(Warning::EmptyRepetition {
+               element: current.unwrap(),
+               pos,
+            });
+         }
+         _ => {}
+      }
+   }
+
+   let mut referenced: Vec<E> = Vec::new();
+   for instruction in instructions {
+      if let Instruction::Reference(element) = *instruction {
+         if !referenced.contains(&element) {
+            referenced.push(element);
+         }
+      }
+   }
+   if !referenced.contains(&root) {
+      referenced.insert(0, root);
+   }
+   for &element in &referenced {
+      if !defined(elements, element.index()) {
+         warnings.push(Warning::Undefined(element));
+      }
+   }
+
+   let mut reachable = vec![false; elements.len()];
+   let mut queue = vec![root.index()];
+   while let Some(index) = queue.pop() {
+      if !defined(elements, index) || reachable[index] {
+         continue;
+      }
+      reachable[index] = true;
+
+      let start = elements[index] + 1;
+      for instruction in &instructions[start..next_sibling(instructions, start)] {
+         if let Instruction::Reference(element) = *instruction {
+            queue.push(element.index());
+         }
+      }
+   }
+   for (pos, instruction) in instructions.iter().enumerate() {
+      match *instruction {
+         Instruction::Element(element) | Instruction::Block(element)
+            if elements[element.index()] == pos && !reachable[element.index()] =>
+         {
+            warnings.push(Warning::Unreachable(element));
+         }
+         _ => {}
+      }
+   }
+
+   for (index, &pos) in elements.iter().enumerate() {
+      if pos == UNDEFINED && !referenced.iter().any(|element| element.index() == index) {
+         warnings.push(Warning::UnusedIndex(index));
+      }
+   }
+
+   warnings
+}
+
+/// Cycles of elements that can reach themselves without consuming a
+/// token, which would make `TokParser` loop forever. Each cycle is listed
+/// once, starting with its earliest defined element.
+pub fn left_recursion<E: ElementId>(
+   instructions: &[Instruction<E>],
+   elements: &[usize],
+) -> Vec<Vec<E>> {
+   let nullable = nullable_elements(instructions, elements);
+
+   let edges: Vec<Vec<usize>> = elements
+      .iter()
+      .map(|&pos| {
+         let mut references = Vec::new();
+         if pos != UNDEFINED {
+            left_references(instructions, &nullable, pos + 1, &mut references);
+         }
+         references
+            .into_iter()
+            .map(|element| element.index())
+            .filter(|&index| defined(elements, index))
+            .collect()
+      })
+      .collect();
+
+   let mut order: Vec<usize> = (0..elements.len())
+      .filter(|&index| defined(elements, index))
+      .collect();
+   order.sort_by_key(|&index| elements[index]);
+
+   let mut reported = vec![false; elements.len()];
+   let mut cycles = Vec::new();
+
+   for &index in &order {
+      if reported[index] {
+         continue;
+      }
+
+      if let Some(cycle) = cycle_from(&edges, index) {
+         for &member in &cycle {
+            reported[member] = true;
+         }
+         cycles.push(
+            cycle
+               .into_iter()
+               .map(|member| element_at(instructions, elements[member]))
+               .collect(),
+         );
+      }
+   }
+
+   cycles
+}
+
+/// Shortest path of edges leading from `start` back to itself.
+fn cycle_from(edges: &[Vec<usize>], start: usize) -> Option<Vec<usize>> {
+   let mut parents = vec![None; edges.len()];
+   let mut queue = vec![start];
+   let mut i = 0;
+
+   while i < queue.len() {
+      let index = queue[i];
+      i += 1;
+
+      for &next in &edges[index] {
+         if next == start {
+            let mut cycle = vec![index];
+            while let Some(parent) = parents[*cycle.last().unwrap()] {
+               cycle.push(parent);
+            }
+            cycle.reverse();
+            return Some(cycle);
+         }
+
+         if parents[next].is_none() && next != start {
+            parents[next] = Some(index);
+            queue.push(next);
+         }
+      }
+   }
+
+   None
+}
+
+/// Whether every element can match without consuming a token, indexed by
+/// `ElementId::index`.
+fn nullable_elements<E: ElementId>(
+   instructions: &[Instruction<E>],
+   elements: &[usize],
+) -> Vec<bool> {
+   let mut nullable = vec![false; elements.len()];
+
+   let mut changed = true;
+   while changed {
+      changed = false;
+      for (index, &pos) in elements.iter().enumerate() {
+         if pos != UNDEFINED && !nullable[index] && nullable_at(instructions, &nullable, pos + 1) {
+            nullable[index] = true;
+            changed = true;
+         }
+      }
+   }
+
+   nullable
+}
+
+fn nullable_at<E: ElementId>(instructions: &[Instruction<E>], nullable: &[bool], i: usize) -> bool {
+   match instructions[i] {
+      Instruction::Tok(_) => false,
+      // Matches nothing at the top level of a module.
+      Instruction::Indentation(_) => true,
+      Instruction::Reference(element) => nullable.get(element.index()) == Some(&true),
+      Instruction::Element(_) | Instruction::Block(_) => nullable_at(instructions, nullable, i + 1),
+      Instruction::Sequence(_) => children(instructions, i)
+         .iter()
+         .all(|&child| nullable_at(instructions, nullable, child)),
+      Instruction::Choice(_) => children(instructions, i)
+         .iter()
+         .any(|&child| nullable_at(instructions, nullable, child)),
+      Instruction::ZeroOrOne(_) | Instruction::ZeroOrMore(_) => true,
+   }
+}
+
+/// Elements referenced by the instruction at `i` before it has consumed a
+/// token.
+fn left_references<E: ElementId>(
+   instructions: &[Instruction<E>],
+   nullable: &[bool],
+   i: usize,
+   references: &mut Vec<E>,
+) {
+   match instructions[i] {
+      Instruction::Tok(_) | Instruction::Indentation(_) => {}
+      Instruction::Reference(element) => references.push(element),
+      Instruction::Element(_) | Instruction::Block(_) => {
+         left_references(instructions, nullable, i + 1, references)
+      }
+      Instruction::Choice(_) => {
+         for child in children(instructions, i) {
+            left_references(instructions, nullable, child, references);
+         }
+      }
+      Instruction::Sequence(_) | Instruction::ZeroOrOne(_) | Instruction::ZeroOrMore(_) => {
+         for child in children(instructions, i) {
+            left_references(instructions, nullable, child, references);
+            if !nullable_at(instructions, nullable, child) {
+               break;
+            }
+         }
+      }
+   }
+}
+
+/// Positions of the instructions directly within the list at `i`.
+fn children<E>(instructions: &[Instruction<E>], i: usize) -> Vec<usize> {
+   let end = match instructions[i] {
+      Instruction::Sequence(end)
+      | Instruction::Choice(end)
+      | Instruction::ZeroOrOne(end)
+      | Instruction::ZeroOrMore(end) => end,
+      _ => return Vec::new(),
+   };
+
+   let mut children = Vec::new();
+   let mut child = i + 1;
+   while child < end {
+      children.push(child);
+      child = next_sibling(instructions, child);
+   }
+   children
+}
+
+fn next_sibling<E>(instructions: &[Instruction<E>], i: usize) -> usize {
+   match instructions[i] {
+      Instruction::Sequence(end)
+      | Instruction::Choice(end)
+      | Instruction::ZeroOrOne(end)
+      | Instruction::ZeroOrMore(end) => end,
+      _ => i + 1,
+   }
+}
+
+fn defined(elements: &[usize], index: usize) -> bool {
+   elements.get(index).is_some_and(|&pos| pos != UNDEFINED)
+}
+
+fn element_at<E: ElementId>(instructions: &[Instruction<E>], pos: usize) -> E {
+   match instructions[pos] {
+      Instruction::Element(element) | Instruction::Block(element) => element,
+      _ => unreachable!(),
+   }
+}
+
+/// Element matched by `TokParser`, covering the tokens from `start` up to
+/// `end`, with the elements matched within it as children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree<E> {
+   pub element: E,
+   pub start: usize,
+   pub end: usize,
+   pub children: Vec<Tree<E>>,
+}
+
+/// Token position and count of completed trees to return to when
+/// backtracking.
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+   tok_pos: usize,
+   nodes: usize,
+}
+
+/// Interprets the instructions of a grammar over a token stream, without
+/// recursion, keeping the path of open instructions on its own stack.
+pub struct TokParser<'b, 't, E: 'b> {
+   instructions: &'b [Instruction<E>],
+   elements: &'b [usize],
+   toks: &'t [Tok],
+   toks_meta: &'t [TokMeta],
+   module_indentation: usize,
+   current_indentation: usize,
+   path: Vec<usize>,
+   elm_pos: usize,
+   tok_pos: usize,
+   marks: Vec<Mark>,
+   nodes: Vec<Tree<E>>,
+   matched: bool,
+   trace: Option<&'t mut dyn Trace>,
+}
+
+impl<'b, 't, E: ElementId> TokParser<'b, 't, E> {
+   /// Parser matching `root` from the start of `toks`.
+   pub fn new(
+      instructions: &'b [Instruction<E>],
+      elements: &'b [usize],
+      root: E,
+      toks: &'t [Tok],
+      toks_meta: &'t [TokMeta],
+      module_indentation: usize,
+   ) -> Self {
+      let path: Vec<usize> = Vec::new();
+      let elm_pos = position(elements, root);
+
+      let tok_pos = 0;
+      let marks: Vec<Mark> = Vec::new();
+      let nodes: Vec<Tree<E>> = Vec::new();
+
+      let current_indentation = 0;
+
+      let matched = false;
+
+      TokParser {
+         instructions,
+         elements,
+         toks,
+         toks_meta,
+         module_indentation,
+         current_indentation,
+         path,
+         elm_pos,
+         tok_pos,
+         marks,
+         nodes,
+         matched,
+         trace: None,
+      }
+   }
+
+   pub fn with_trace(mut self, trace: &'t mut dyn Trace) -> Self {
+      self.trace = Some(trace);
+      self
+   }
+
+   /// Tree of the root element, if it matched at all. It does not
+   /// necessarily cover all of the tokens.
+   pub fn parse(&mut self) -> Option<Tree<E>> {
+      loop {
+         if self.process_next() && self.try_finalize() {
+            break;
+         }
+      }
+
+      debug_assert!(self.nodes.len() == self.matched as usize);
+
+      self.nodes.pop()
+   }
+
+   fn process_next(&mut self) -> bool {
+      match self.instructions[self.elm_pos] {
+         Instruction::Element(element) => self.process_element(element, false),
+         Instruction::Block(element) => self.process_element(element, true),
+         Instruction::Sequence(end)
+         | Instruction::ZeroOrOne(end)
+         | Instruction::ZeroOrMore(end)
+         | Instruction::Choice(end) => self.process_list(end),
+         Instruction::Reference(element) => self.process_reference(element),
+         Instruction::Tok(ref tok) => self.process_tok(tok),
+         Instruction::Indentation(indentation) => self.process_indentation(indentation),
+      }
+   }
+
+   fn process_element(&mut self, element: E, block: bool) -> bool {
+      if let Some(ref mut trace) = self.trace {
+         trace.enter(&format!("{:?}", element), self.tok_pos);
+      }
+      if block {
+         self.current_indentation += 1;
+      }
+      self.path.push(self.elm_pos);
+      self.mark();
+      self.elm_pos += 1;
+
+      false
+   }
+
+   fn process_list(&mut self, end: usize) -> bool {
+      debug_assert!(self.elm_pos < end);
+      self.path.push(self.elm_pos);
+      self.mark();
+      self.elm_pos += 1;
+
+      false
+   }
+
+   fn process_reference(&mut self, element: E) -> bool {
+      if self.tok_pos == self.toks.len() {
+         self.matched = false;
+         self.elm_pos += 1;
+
+         true
+      } else {
+         self.path.push(self.elm_pos);
+         self.elm_pos = position(self.elements, element);
+
+         false
+      }
+   }
+
+   fn process_tok(&mut self, tok: &Tok) -> bool {
+      self.matched = if let Some(tok_src) = self.toks.get(self.tok_pos) {
+         let equal = tok == tok_src;
+         if equal {
+            self.consume_tok();
+         }
+         equal
+      } else {
+         false
+      };
+
+      self.elm_pos += 1;
+
+      true
+   }
+
+   fn process_indentation(&mut self, indentation: usize) -> bool {
+      let spaces = (self.current_indentation + indentation) * self.module_indentation;
+
+      self.matched = if spaces == 0 {
+         true
+      } else if let Some(tok_src) = self.toks.get(self.tok_pos) {
+         let span = self.toks_meta[self.tok_pos].span;
+         let equal = tok_src == &Tok::Space && span == spaces;
+         if equal {
+            self.consume_tok();
+         }
+         equal
+      } else {
+         false
+      };
+
+      self.elm_pos += 1;
+
+      true
+   }
+
+   fn try_finalize(&mut self) -> bool {
+      loop {
+         if !self.path.is_empty() {
+            let pos = *self.path.last().unwrap();
+
+            let exit = match self.instructions[pos] {
+               Instruction::Element(element) => self.try_finalize_element(element, false),
+               Instruction::Block(element) => self.try_finalize_element(element, true),
+               Instruction::Reference(_) => self.try_finalize_reference(pos),
+               Instruction::Sequence(end) => self.try_finalize_sequence(end, pos),
+               Instruction::Choice(end) => self.try_finalize_choice(end, pos),
+               Instruction::ZeroOrOne(end) => self.try_finalize_zero_or_one(end, pos),
+               Instruction::ZeroOrMore(end) => self.try_finalize_zero_or_more(end, pos),
+               _ => unreachable!(),
+            };
+
+            if exit {
+               return false;
+            }
+         } else {
+            return true;
+         }
+      }
+   }
+
+   fn try_finalize_element(&mut self, element: E, block: bool) -> bool {
+      if block {
+         self.current_indentation -= 1;
+      }
+      self.path.pop();
+      let mark = self.pop_mark();
+
+      if let Some(ref mut trace) = self.trace {
+         let name = format!("{:?}", element);
+         if self.matched {
+            trace.exit(&name, mark.tok_pos, self.tok_pos);
+         } else {
+            trace.fail(&name, mark.tok_pos, self.tok_pos);
+         }
+      }
+
+      if self.matched {
+         let children = self.nodes.split_off(mark.nodes);
+         self.nodes.push(Tree {
+            element,
+            start: mark.tok_pos,
+            end: self.tok_pos,
+            children,
+         });
+      } else {
+         debug_assert!(mark.tok_pos == self.tok_pos);
+         self.restore(mark);
+      }
+
+      false
+   }
+
+   fn consume_tok(&mut self) {
+      if let Some(ref mut trace) = self.trace {
+         trace.matched(&self.toks[self.tok_pos], self.tok_pos);
+      }
+      self.tok_pos += 1;
+   }
+
+   fn try_finalize_reference(&mut self, pos: usize) -> bool {
+      self.elm_pos = pos + 1;
+      self.path.pop();
+
+      false
+   }
+
+   fn try_finalize_sequence(&mut self, end: usize, _pos: usize) -> bool {
+      if !self.matched {
+         self.elm_pos = end;
+         self.path.pop();
+         let mark = self.pop_mark();
+         self.restore(mark);
+
+         false
+      } else if self.elm_pos == end {
+         self.path.pop();
+         self.pop_mark();
+
+         false
+      } else {
+         true
+      }
+   }
+
+   fn try_finalize_choice(&mut self, end: usize, _pos: usize) -> bool {
+      if self.matched {
+         self.elm_pos = end;
+         self.path.pop();
+         self.pop_mark();
+
+         false
+      } else if self.elm_pos == end {
+         self.path.pop();
+         let mark = self.pop_mark();
+         self.restore(mark);
+
+         false
+      } else {
+         let mark = self.last_mark();
+         self.restore(mark);
+
+         true
+      }
+   }
+
+   fn try_finalize_zero_or_one(&mut self, end: usize, _pos: usize) -> bool {
+      if !self.matched {
+         self.elm_pos = end;
+         self.path.pop();
+         let mark = self.pop_mark();
+         self.restore(mark);
+         self.matched = true;
+
+         false
+      } else if self.elm_pos == end {
+         self.path.pop();
+         self.pop_mark();
+
+         false
+      } else {
+         true
+      }
+   }
+
+   fn try_finalize_zero_or_more(&mut self, end: usize, pos: usize) -> bool {
+      if !self.matched {
+         self.elm_pos = end;
+         self.path.pop();
+         let mark = self.pop_mark();
+         self.restore(mark);
+         self.matched = true;
+
+         false
+      } else if self.elm_pos == end {
+         self.elm_pos = pos + 1;
+         self.pop_mark();
+         self.mark();
+
+         true
+      } else {
+         true
+      }
+   }
+
+   fn mark(&mut self) {
+      self.marks.push(Mark {
+         tok_pos: self.tok_pos,
+         nodes: self.nodes.len(),
+      });
+   }
+
+   fn last_mark(&self) -> Mark {
+      if let Some(mark) = self.marks.last() {
+         *mark
+      } else {
+         unreachable!();
+      }
+   }
+
+   fn pop_mark(&mut self) -> Mark {
+      if let Some(mark) = self.marks.pop() {
+         mark
+      } else {
+         unreachable!();
+      }
+   }
+
+   /// Backtracks to `mark`, dropping the trees completed since.
+   fn restore(&mut self, mark: Mark) {
+      self.tok_pos = mark.tok_pos;
+      self.nodes.truncate(mark.nodes);
+   }
+}
+
+fn position<E: ElementId>(elements: &[usize], element: E) -> usize {
+   match elements.get(element.index()) {
+      Some(&pos) if pos != UNDEFINED => pos,
+      _ => panic!("Undefined grammar element: {:?}", element),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use tokenize::tokenize_str;
+
+   #[derive(Debug, Clone, Copy, PartialEq)]
+   enum Element {
+      Module,
+      Statement,
+      If,
+      Block,
+      Expression,
+      Operand,
+   }
+
+   impl ElementId for Element {
+      fn index(self) -> usize {
+         self as usize
+      }
+   }
+
+   #[rustfmt::skip]
+   fn grammar() -> (Vec<Instruction<Element>>, Vec<usize>) {
+      let mut b = Builder::new();
+
+      b.element(Element::Module)
+         .zero_or_more()
+            .choice()
+               .reference(Element::Statement)
+               .tok(Tok::LineEnd)
+            .end()
+         .end();
+
+      b.element(Element::Statement)
+         .sequence()
+            .indentation(0)
+            .choice()
+               .reference(Element::If)
+               .sequence()
+                  .reference(Element::Expression)
+                  .tok(Tok::LineEnd)
+               .end()
+            .end()
+         .end();
+
+      b.element(Element::If)
+         .sequence()
+            .tok(Tok::If)
+            .skip_space()
+            .reference(Element::Expression)
+            .tok(Tok::LineEnd)
+            .reference(Element::Block)
+         .end();
+
+      b.block(Element::Block)
+         .sequence()
+            .reference(Element::Statement)
+            .zero_or_more()
+               .reference(Element::Statement)
+            .end()
+         .end();
+
+      b.element(Element::Expression)
+         .sequence()
+            .reference(Element::Operand)
+            .zero_or_more()
+               .sequence()
+                  .skip_space()
+                  .choice()
+                     .tok(Tok::Plus)
+                     .tok(Tok::And)
+                  .end()
+                  .skip_space()
+                  .reference(Element::Operand)
+               .end()
+            .end()
+         .end();
+
+      b.element(Element::Operand)
+         .choice()
+            .tok(Tok::Identifier)
+            .tok(Tok::Digits)
+         .end();
+
+      b.destructure()
+   }
+
+   fn parse(source: &str) -> Option<Tree<Element>> {
+      let (instructions, elements) = grammar();
+      let (toks, toks_meta, _) = tokenize_str(source);
+      TokParser::new(
+         &instructions,
+         &elements,
+         Element::Module,
+         &toks,
+         &toks_meta,
+         3,
+      )
+      .parse()
+   }
+
+   #[test]
+   fn test_builder() {
+      let (instructions, elements) = grammar();
+      assert_eq!(elements.len(), 6);
+      match instructions[elements[Element::Block.index()]] {
+         Instruction::Block(Element::Block) => {}
+         ref other => panic!("Unexpected instruction {:?}", other),
+      }
+      match instructions[elements[Element::If.index()] + 1] {
+         Instruction::Sequence(end) => assert_eq!(end, elements[Element::Block.index()]),
+         ref other => panic!("Unexpected instruction {:?}", other),
+      }
+   }
+
+   fn end(source: &str) -> Option<usize> {
+      parse(source).map(|tree| tree.end)
+   }
+
+   /// Elements of the tree with their token ranges, children in brackets.
+   fn shape(tree: &Tree<Element>) -> String {
+      let node = format!("{:?}{}-{}", tree.element, tree.start, tree.end);
+      if tree.children.is_empty() {
+         return node;
+      }
+      let children: Vec<_> = tree.children.iter().map(shape).collect();
+      format!("{}[{}]", node, children.join(" "))
+   }
+
+   #[test]
+   fn test_parse() {
+      assert_eq!(end("a + 1\n"), Some(6));
+      assert_eq!(end("if a and b\n   c\n   1 + d\ne\n"), Some(20));
+      assert_eq!(end("\n"), Some(1));
+   }
+
+   #[test]
+   fn test_parse_partial() {
+      assert_eq!(end("a\nif b\nc\n"), Some(2));
+      assert_eq!(end("a +\n"), Some(0));
+   }
+
+   #[test]
+   fn test_tree() {
+      assert_eq!(
+         shape(&parse("a + 1\n").unwrap()),
+         "Module0-6[Statement0-6[Expression0-5[Operand0-1 Operand4-5]]]"
+      );
+      assert_eq!(
+         shape(&parse("if a\n   b and c\n\nd\n").unwrap()),
+         "Module0-14[\
+          Statement0-11[If0-11[Expression2-3[Operand2-3] \
+          Block4-11[Statement4-11[Expression5-10[Operand5-6 Operand9-10]]]]] \
+          Statement12-14[Expression12-13[Operand12-13]]]"
+      );
+   }
+
+   #[test]
+   fn test_tree_backtracking() {
+      // `If` fails after matching its condition and `Statement` falls back
+      // to the expression alternative, which fails as well.
+      assert_eq!(shape(&parse("if a\nb\n").unwrap()), "Module0-0");
+      // The trailing `+` is dropped together with the partial operand
+      // sequence of the last repetition.
+      assert_eq!(shape(&parse("a + b +\n").unwrap()), "Module0-0");
+      assert_eq!(
+         shape(&parse("a + b\nc +\n").unwrap()),
+         "Module0-6[Statement0-6[Expression0-5[Operand0-1 Operand4-5]]]"
+      );
+   }
+
+   #[test]
+   #[rustfmt::skip]
+   fn test_left_recursion() {
+      let (instructions, elements) = grammar();
+      assert!(left_recursion(&instructions, &elements).is_empty());
+
+      let mut b = Builder::new();
+      b.element(Element::Module)
+         .reference(Element::Expression);
+      b.element(Element::Expression)
+         .sequence()
+            .zero_or_one()
+               .tok(Tok::Not)
+            .end()
+            .reference(Element::Operand)
+            .tok(Tok::Plus)
+         .end();
+      b.element(Element::Operand)
+         .choice()
+            .tok(Tok::Digits)
+            .reference(Element::Expression)
+         .end();
+      b.element(Element::If)
+         .sequence()
+            .reference(Element::If)
+         .end();
+      b.element(Element::Statement)
+         .sequence()
+            .tok(Tok::LineEnd)
+            .reference(Element::Statement)
+         .end();
+      let (instructions, elements) = b.destructure();
+
+      assert_eq!(
+         left_recursion(&instructions, &elements),
+         vec![vec![Element::Expression, Element::Operand], vec![Element::If]]
+      );
+   }
+
+   #[test]
+   #[rustfmt::skip]
+   fn test_analyze() {
+      let (instructions, elements) = grammar();
+      assert_eq!(analyze(&instructions, &elements, Element::Module), vec![]);
+      assert_eq!(
+         analyze(&instructions, &elements, Element::If),
+         vec![Warning::Unreachable(Element::Module)]
+      );
+
+      let mut b = Builder::new();
+      b.element(Element::Module)
+         .sequence()
+            .zero_or_more()
+               .reference(Element::Statement)
+            .end()
+            .reference(Element::Block)
+         .end();
+      b.element(Element::Statement)
+         .zero_or_more()
+            .zero_or_one()
+               .tok(Tok::LineEnd)
+            .end()
+            .indentation(0)
+         .end();
+      b.element(Element::Operand)
+         .sequence()
+            .reference(Element::Operand)
+            .tok(Tok::Digits)
+         .end();
+      let (instructions, elements) = b.destructure();
+
+      assert_eq!(
+         analyze(&instructions, &elements, Element::Module),
+         vec![
+            Warning::LeftRecursion(vec![Element::Operand]),
+            Warning::EmptyRepetition { element: Element::Module, pos: 2 },
+            Warning::EmptyRepetition { element: Element::Statement, pos: 6 },
+            Warning::Undefined(Element::Block),
+            Warning::Unreachable(Element::Operand),
+            Warning::UnusedIndex(2),
+            Warning::UnusedIndex(4),
+         ]
+      );
+   }
+
+   #[test]
+   #[should_panic(expected = "Undefined grammar element: Module")]
+   fn test_undefined() {
+      let b: Builder<Element> = Builder::new();
+      let (instructions, elements) = b.destructure();
+      TokParser::new(&instructions, &elements, Element::Module, &[], &[], 3);
+   }
+}
@@ -1,25 +1,56 @@
+use std::cmp::Reverse;
 use std::collections::BTreeMap;
+use std::ops::Range;
 
 use tokenize::{Tok, TokMeta};
 
-struct IndentationEstimator {
+/// Whether the line starting at token `i` holds nothing but a comment.
+pub fn blank_line(toks: &[Tok], i: usize) -> bool {
+   match toks[i] {
+      Tok::LineEnd | Tok::Comment => true,
+      Tok::Space => toks.get(i + 1) == Some(&Tok::Comment),
+      _ => false,
+   }
+}
+
+/// Outcome of `IndentationEstimator::estimate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+   /// The chosen indentation unit, `0` when no line is indented.
+   pub indentation: usize,
+   /// Indentation changes agreeing with the unit minus those disagreeing.
+   pub score: isize,
+   /// Every other candidate unit with its score, best first.
+   pub alternatives: Vec<(usize, isize)>,
+   /// Share of indentation changes agreeing with the chosen unit.
+   pub confidence: f64,
+}
+
+impl Estimate {
+   /// Best candidate other than the chosen unit.
+   pub fn runner_up(&self) -> Option<(usize, isize)> {
+      self.alternatives.first().cloned()
+   }
+}
+
+pub struct IndentationEstimator {
    deltas: BTreeMap<isize, isize>,
 }
 
 impl IndentationEstimator {
-   fn new() -> Self {
+   pub fn new() -> Self {
       IndentationEstimator {
          deltas: BTreeMap::new(),
       }
    }
 
-   fn count(mut self, toks: &[Tok], toks_meta: &[TokMeta], line_starts: &[usize]) -> Self {
+   pub fn count(mut self, toks: &[Tok], toks_meta: &[TokMeta], line_starts: &[usize]) -> Self {
       let mut prev_space_span = 0;
 
       for i in line_starts {
          let tok = &toks[*i];
          let tok_meta = &toks_meta[*i];
-         if tok == &Tok::Space {
+         if tok == &Tok::Space && !blank_line(toks, *i) {
             let delta = tok_meta.span as isize - prev_space_span as isize;
 
             if delta != 0 {
@@ -37,15 +68,18 @@ impl IndentationEstimator {
       self
    }
 
-   fn estimate(&self) -> usize {
-      let mut target_max = 0;
-      let mut matches_max = ::std::isize::MIN;
+   pub fn estimate(&self) -> Estimate {
+      let mut candidates: Vec<(usize, isize)> = vec![];
 
       for target in self.deltas.keys().rev() {
          let mut matches: isize = 0;
 
          let target = target.abs();
 
+         if candidates.iter().any(|c| c.0 == target as usize) {
+            continue;
+         }
+
          for (delta, count) in &self.deltas {
             let matched = if *delta > 0 {
                *delta == target || *delta == 2 * target
@@ -61,13 +95,36 @@ impl IndentationEstimator {
             }
          }
 
-         if matches > matches_max {
-            target_max = target;
-            matches_max = matches;
-         }
+         candidates.push((target as usize, matches));
       }
 
-      target_max as usize
+      candidates.sort_by_key(|c| Reverse(c.1));
+
+      let total: isize = self.deltas.values().sum();
+
+      if candidates.is_empty() {
+         return Estimate {
+            indentation: 0,
+            score: 0,
+            alternatives: candidates,
+            confidence: 1.0,
+         };
+      }
+
+      let (indentation, score) = candidates.remove(0);
+
+      Estimate {
+         indentation,
+         score,
+         alternatives: candidates,
+         confidence: (total + score) as f64 / (2 * total) as f64,
+      }
+   }
+}
+
+impl Default for IndentationEstimator {
+   fn default() -> Self {
+      IndentationEstimator::new()
    }
 }
 
@@ -75,6 +132,276 @@ pub fn estimate_indentation(toks: &[Tok], toks_meta: &[TokMeta], line_starts: &[
    IndentationEstimator::new()
       .count(toks, toks_meta, line_starts)
       .estimate()
+      .indentation
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+   /// Indentation is not a multiple of the indentation unit.
+   NotMultiple,
+   /// Indentation opens more than one block level at once and is not a
+   /// double indented continuation line. The line is taken to open a
+   /// single level for the lines that follow.
+   OverIndented,
+   /// A `# lax: indent=N` directive with a missing or zero unit.
+   InvalidDirective,
+   /// A configured unit of zero.
+   InvalidConfig,
+   /// The directive sets a different unit than the configured one.
+   Overridden { config: usize },
+   /// The explicit indentation unit differs from the estimated one.
+   Conflict { estimated: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+   pub issue: Issue,
+   pub tok: usize,
+   pub line: usize,
+   pub col: usize,
+}
+
+struct IndentationValidator {
+   indentation: usize,
+   levels: Vec<usize>,
+   continuation: bool,
+   diagnostics: Vec<Diagnostic>,
+}
+
+impl IndentationValidator {
+   fn new(indentation: usize) -> Self {
+      IndentationValidator {
+         indentation,
+         levels: vec![0],
+         continuation: false,
+         diagnostics: vec![],
+      }
+   }
+
+   fn validate(mut self, toks: &[Tok], toks_meta: &[TokMeta], line_starts: &[usize]) -> Self {
+      if self.indentation == 0 {
+         return self;
+      }
+
+      for i in line_starts {
+         if blank_line(toks, *i) {
+            continue;
+         }
+
+         let spaces = match toks[*i] {
+            Tok::Space => toks_meta[*i].span,
+            _ => 0,
+         };
+
+         if let Some(issue) = self.line(spaces) {
+            self.diagnostics.push(Diagnostic {
+               issue,
+               tok: *i,
+               line: toks_meta[*i].line,
+               col: spaces + 1,
+            });
+         }
+      }
+
+      self
+   }
+
+   // `usize::is_multiple_of` would need Rust 1.87.
+   #[allow(clippy::manual_is_multiple_of)]
+   fn line(&mut self, spaces: usize) -> Option<Issue> {
+      if spaces % self.indentation != 0 {
+         return Some(Issue::NotMultiple);
+      }
+
+      let level = spaces / self.indentation;
+      let top = *self.levels.last().unwrap();
+
+      if self.continuation && level == top + 2 {
+         return None;
+      }
+
+      self.continuation = level == top + 2;
+
+      if level == top + 1 {
+         self.levels.push(level);
+      } else if level > top + 2 {
+         self.levels.push(top + 1);
+         return Some(Issue::OverIndented);
+      } else if level < top {
+         // Levels only ever open one at a time, so any shallower level
+         // belongs to an enclosing block.
+         self.levels.truncate(level + 1);
+      }
+
+      None
+   }
+}
+
+/// Checks every line against the `indentation` unit, usually the one
+/// returned by `estimate_indentation`.
+pub fn validate_indentation(
+   toks: &[Tok],
+   toks_meta: &[TokMeta],
+   line_starts: &[usize],
+   indentation: usize,
+) -> Vec<Diagnostic> {
+   IndentationValidator::new(indentation)
+      .validate(toks, toks_meta, line_starts)
+      .diagnostics
+}
+
+/// Where the indentation unit of a module came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+   Directive,
+   Config,
+   Estimate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleIndentation {
+   pub indentation: usize,
+   pub origin: Origin,
+   pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Looks for a `# lax: indent=N` comment among the comment lines heading
+/// the module. Returns the comment token index and the unit, `None` as the
+/// unit when the directive cannot be read.
+pub fn indentation_directive(
+   chars: &[char],
+   toks: &[Tok],
+   toks_meta: &[TokMeta],
+   line_starts: &[usize],
+) -> Option<(usize, Option<usize>)> {
+   for i in line_starts {
+      let i = if toks[*i] == Tok::Space { *i + 1 } else { *i };
+
+      match toks[i] {
+         Tok::LineEnd => continue,
+         Tok::Comment => {}
+         _ => return None,
+      }
+
+      if let Some(value) = indent_setting(chars, &toks_meta[i]) {
+         let value: String = chars[value].iter().collect();
+         let indentation = value.parse().ok().filter(|unit| *unit > 0);
+         return Some((i, indentation));
+      }
+   }
+
+   None
+}
+
+/// Chars holding the value of the `indent=` setting when the comment is a
+/// `lax:` directive, its settings separated by whitespace.
+pub fn indent_setting(chars: &[char], comment: &TokMeta) -> Option<Range<usize>> {
+   let end = comment.end;
+   let mut pos = comment.end - comment.span + 1;
+
+   while pos < end && chars[pos].is_whitespace() {
+      pos += 1;
+   }
+
+   let prefix = ['l', 'a', 'x', ':'];
+   if !chars[pos..end].starts_with(&prefix) {
+      return None;
+   }
+   pos += prefix.len();
+
+   let key = ['i', 'n', 'd', 'e', 'n', 't', '='];
+
+   while pos < end {
+      if chars[pos].is_whitespace() {
+         pos += 1;
+         continue;
+      }
+
+      let start = pos;
+      while pos < end && !chars[pos].is_whitespace() {
+         pos += 1;
+      }
+
+      if chars[start..pos].starts_with(&key) {
+         return Some(start + key.len()..pos);
+      }
+   }
+
+   None
+}
+
+/// Resolves the indentation unit of a module. A directive in the source
+/// takes precedence over `config`, which in turn takes precedence over the
+/// estimate. A directive disagreeing with `config` is reported as
+/// `Overridden`, and an explicit unit disagreeing with the estimate as a
+/// `Conflict`, followed by the lines not conforming to the chosen unit. A
+/// zero unit in `config` is reported and ignored, like an invalid
+/// directive.
+pub fn module_indentation(
+   chars: &[char],
+   toks: &[Tok],
+   toks_meta: &[TokMeta],
+   line_starts: &[usize],
+   config: Option<usize>,
+) -> ModuleIndentation {
+   let estimated = estimate_indentation(toks, toks_meta, line_starts);
+
+   let mut diagnostics = vec![];
+
+   let directive = indentation_directive(chars, toks, toks_meta, line_starts);
+
+   let position = |i: usize, issue: Issue| Diagnostic {
+      issue,
+      tok: i,
+      line: toks_meta[i].line,
+      col: toks_meta[i].col,
+   };
+
+   let config = match config {
+      Some(0) => {
+         diagnostics.push(position(0, Issue::InvalidConfig));
+         None
+      }
+      config => config,
+   };
+
+   let (indentation, origin) = match (directive, config) {
+      (Some((i, Some(indentation))), config) => {
+         if let Some(config) = config.filter(|config| *config != indentation) {
+            diagnostics.push(position(i, Issue::Overridden { config }));
+         }
+         if estimated != 0 && estimated != indentation {
+            diagnostics.push(position(i, Issue::Conflict { estimated }));
+         }
+         (indentation, Origin::Directive)
+      }
+      (directive, config) => {
+         if let Some((i, None)) = directive {
+            diagnostics.push(position(i, Issue::InvalidDirective));
+         }
+         match config {
+            Some(indentation) => (indentation, Origin::Config),
+            None => (estimated, Origin::Estimate),
+         }
+      }
+   };
+
+   if origin == Origin::Config && estimated != 0 && estimated != indentation {
+      diagnostics.push(position(0, Issue::Conflict { estimated }));
+   }
+
+   diagnostics.extend(validate_indentation(
+      toks,
+      toks_meta,
+      line_starts,
+      indentation,
+   ));
+
+   ModuleIndentation {
+      indentation,
+      origin,
+      diagnostics,
+   }
 }
 
 #[cfg(test)]
@@ -91,7 +418,7 @@ mod tests {
          let estimated = IndentationEstimator::new()
             .count(&toks, &toks_meta, &line_starts)
             .estimate();
-         assert_eq!(estimated, $expected);
+         assert_eq!(estimated.indentation, $expected);
       };
    }
 
@@ -218,4 +545,248 @@ mod tests {
          2
       );
    }
+
+   fn estimate(source: &str) -> Estimate {
+      let chars: Vec<_> = source.chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      IndentationEstimator::new()
+         .count(&toks, &toks_meta, &line_starts)
+         .estimate()
+   }
+
+   #[test]
+   fn test_estimate_confident() {
+      let estimate = estimate(indoc!(
+         "
+         x
+            x
+               x
+            x
+         x
+         "
+      ));
+      assert_eq!(estimate.indentation, 3);
+      assert_eq!(estimate.score, 3);
+      assert_eq!(estimate.confidence, 1.0);
+      assert_eq!(estimate.runner_up(), None);
+   }
+
+   #[test]
+   fn test_estimate_ambiguous() {
+      let estimate = estimate(indoc!(
+         "
+         x
+            x
+              x
+            x
+         "
+      ));
+      assert_eq!(estimate.indentation, 2);
+      assert_eq!(estimate.score, 1);
+      assert_eq!(estimate.alternatives, vec![(3, -1)]);
+      assert!(estimate.confidence < 0.7);
+   }
+
+   #[test]
+   fn test_estimate_empty() {
+      let estimate = estimate("x\nx\n");
+      assert_eq!(estimate.indentation, 0);
+      assert_eq!(estimate.alternatives, vec![]);
+      assert_eq!(estimate.confidence, 1.0);
+   }
+
+   macro_rules! assert_diagnostics {
+      ($string:tt, $indentation:expr, [$(($issue:ident, $line:expr, $col:expr)),*]) => {
+         let source = indoc!($string);
+         let chars: Vec<_> = source.chars().collect();
+         let (toks, toks_meta, line_starts) = tokenize(&chars);
+         let found: Vec<_> = validate_indentation(&toks, &toks_meta, &line_starts, $indentation)
+            .into_iter()
+            .map(|d| (d.issue, d.line, d.col))
+            .collect();
+         let expected: Vec<(Issue, usize, usize)> = vec![$((Issue::$issue, $line, $col)),*];
+         assert_eq!(found, expected);
+      };
+   }
+
+   #[test]
+   fn test_validate_valid() {
+      assert_diagnostics!(
+         "
+         if x
+               and y
+
+               and z
+            x
+            if y
+               y
+         x
+         ",
+         3,
+         []
+      );
+   }
+
+   #[test]
+   fn test_validate_not_multiple() {
+      assert_diagnostics!(
+         "
+         x
+            x
+              x
+            x
+         ",
+         2,
+         [
+            (NotMultiple, 2, 4),
+            (NotMultiple, 3, 6),
+            (NotMultiple, 4, 4)
+         ]
+      );
+   }
+
+   #[test]
+   fn test_validate_over_indented() {
+      assert_diagnostics!(
+         "
+         x
+                  x
+                  x
+               x
+            x
+         ",
+         3,
+         [(OverIndented, 2, 10)]
+      );
+   }
+
+   #[test]
+   fn test_validate_over_indented_nested() {
+      assert_diagnostics!(
+         "
+         x
+            x
+               x
+                        x
+                  x
+         x
+         ",
+         3,
+         [(OverIndented, 4, 16)]
+      );
+   }
+
+   #[test]
+   fn test_validate_no_indentation() {
+      assert_diagnostics!("x\nx\n", 0, []);
+   }
+
+   fn resolve(source: &str, config: Option<usize>) -> ModuleIndentation {
+      let chars: Vec<_> = source.chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      module_indentation(&chars, &toks, &toks_meta, &line_starts, config)
+   }
+
+   #[test]
+   fn test_directive() {
+      let source = indoc!(
+         "
+         # Module header
+
+         #  lax:  indent=4
+         x
+             x
+               x
+         "
+      );
+      let module = resolve(source, Some(2));
+      assert_eq!(module.indentation, 4);
+      assert_eq!(module.origin, Origin::Directive);
+      let issues: Vec<_> = module.diagnostics.iter().map(|d| &d.issue).collect();
+      assert_eq!(
+         issues,
+         vec![
+            &Issue::Overridden { config: 2 },
+            &Issue::Conflict { estimated: 2 },
+            &Issue::NotMultiple,
+         ]
+      );
+      assert_eq!(module.diagnostics[0].line, 3);
+      let module = resolve(source, Some(4));
+      assert_eq!(module.diagnostics.len(), 2);
+   }
+
+   #[test]
+   fn test_directive_conflict() {
+      let source = indoc!(
+         "
+         # lax: indent=4
+         x
+            x
+               # comment
+                  x
+         "
+      );
+      let module = resolve(source, None);
+      assert_eq!(module.indentation, 4);
+      assert_eq!(
+         module.diagnostics[0],
+         Diagnostic {
+            issue: Issue::Conflict { estimated: 3 },
+            tok: 0,
+            line: 1,
+            col: 1,
+         }
+      );
+      assert_eq!(module.diagnostics.len(), 3);
+   }
+
+   #[test]
+   fn test_directive_after_code() {
+      let module = resolve("x\n# lax: indent=4\n   x\n", None);
+      assert_eq!(module.indentation, 3);
+      assert_eq!(module.origin, Origin::Estimate);
+      assert!(module.diagnostics.is_empty());
+   }
+
+   #[test]
+   fn test_directive_invalid() {
+      let module = resolve("# lax: indent=0\nx\n  x\n", None);
+      assert_eq!(module.indentation, 2);
+      assert_eq!(module.diagnostics[0].issue, Issue::InvalidDirective);
+      let module = resolve("# lax: indent=x\nx\n  x\n", Some(2));
+      assert_eq!(module.origin, Origin::Config);
+      assert_eq!(module.diagnostics[0].issue, Issue::InvalidDirective);
+   }
+
+   #[test]
+   fn test_config_invalid() {
+      let module = resolve("x\n  x\n", Some(0));
+      assert_eq!(module.indentation, 2);
+      assert_eq!(module.origin, Origin::Estimate);
+      assert_eq!(
+         module.diagnostics,
+         vec![Diagnostic {
+            issue: Issue::InvalidConfig,
+            tok: 0,
+            line: 1,
+            col: 1,
+         }]
+      );
+      let module = resolve("# lax: indent=3\nx\n   x\n", Some(0));
+      assert_eq!(module.origin, Origin::Directive);
+      assert_eq!(module.diagnostics[0].issue, Issue::InvalidConfig);
+      assert_eq!(module.diagnostics.len(), 1);
+   }
+
+   #[test]
+   fn test_config_conflict() {
+      let module = resolve("x\n  x\n", Some(3));
+      assert_eq!(module.origin, Origin::Config);
+      assert_eq!(
+         module.diagnostics[0].issue,
+         Issue::Conflict { estimated: 2 }
+      );
+      assert_eq!(module.diagnostics[1].issue, Issue::NotMultiple);
+   }
 }
@@ -3,5 +3,18 @@
 extern crate indoc;
 
 pub mod advancer;
+pub mod ast;
+pub mod blocks;
+pub mod cst;
+pub mod dispatch;
+pub mod grammar;
 pub mod indentation;
+pub mod memo;
+pub mod parse;
+pub mod peg;
+pub mod reindent;
+pub mod reparse;
+pub mod scan;
+pub mod scope;
 pub mod tokenize;
+pub mod trace;
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use advancer::Advancer;
+
+/// Packrat memo table for grammars written on top of `Advancer`.
+///
+/// Results are cached by rule id and peek position, so a rule retried at the
+/// same position by ordered choice is evaluated only once. A rule that fails
+/// leaves the advancer at the position where it was invoked.
+pub struct Memo<R> {
+   enabled: bool,
+   table: HashMap<(usize, usize), Option<(R, usize)>>,
+}
+
+impl<R: Clone> Memo<R> {
+   pub fn new() -> Self {
+      Memo {
+         enabled: true,
+         table: HashMap::new(),
+      }
+   }
+
+   /// A memo that never caches, for running the same grammar without
+   /// memoization.
+   pub fn disabled() -> Self {
+      Memo {
+         enabled: false,
+         table: HashMap::new(),
+      }
+   }
+
+   pub fn len(&self) -> usize {
+      self.table.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.table.is_empty()
+   }
+
+   pub fn clear(&mut self) {
+      self.table.clear();
+   }
+
+   pub fn rule<T, F>(&mut self, advancer: &mut Advancer<T>, id: usize, f: F) -> Option<R>
+   where
+      F: FnOnce(&mut Advancer<T>, &mut Self) -> Option<R>,
+   {
+      let pos = advancer.pos();
+
+      if !self.enabled {
+         let result = f(advancer, self);
+         if result.is_none() {
+            advancer.seek(pos);
+         }
+         return result;
+      }
+
+      if let Some(entry) = self.table.get(&(id, pos)) {
+         return match *entry {
+            Some((ref result, end)) => {
+               advancer.seek(end);
+               Some(result.clone())
+            }
+            None => {
+               advancer.seek(pos);
+               None
+            }
+         };
+      }
+
+      let result = f(advancer, self);
+
+      let entry = match result {
+         Some(ref result) => Some((result.clone(), advancer.pos())),
+         None => {
+            advancer.seek(pos);
+            None
+         }
+      };
+
+      self.table.insert((id, pos), entry);
+
+      result
+   }
+}
+
+impl<R: Clone> Default for Memo<R> {
+   fn default() -> Self {
+      Memo::new()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   use std::cell::Cell;
+
+   const EXPR: usize = 0;
+   const TERM: usize = 1;
+
+   // expr := term '+' expr / term '-' expr / term
+   // term := '(' expr ')' / 'x'
+   fn expr(a: &mut Advancer<char>, memo: &mut Memo<usize>, calls: &Cell<usize>) -> Option<usize> {
+      memo.rule(a, EXPR, |a, memo| {
+         calls.set(calls.get() + 1);
+         let pos = a.pos();
+
+         for op in &['+', '-'] {
+            if let Some(left) = term(a, memo, calls) {
+               if a.one(*op).is_some() {
+                  if let Some(right) = expr(a, memo, calls) {
+                     return Some(left + right);
+                  }
+               }
+            }
+            a.seek(pos);
+         }
+
+         term(a, memo, calls)
+      })
+   }
+
+   fn term(a: &mut Advancer<char>, memo: &mut Memo<usize>, calls: &Cell<usize>) -> Option<usize> {
+      memo.rule(a, TERM, |a, memo| {
+         calls.set(calls.get() + 1);
+         let pos = a.pos();
+
+         if a.one('(').is_some() {
+            let inner = expr(a, memo, calls)?;
+            a.one(')')?;
+            return Some(inner);
+         }
+
+         a.seek(pos);
+         a.one('x')?;
+         Some(1)
+      })
+   }
+
+   fn parse(source: &str, mut memo: Memo<usize>) -> (Option<usize>, usize, usize) {
+      let chars: Vec<_> = source.chars().collect();
+      let mut advancer = Advancer::new(&chars);
+      let calls = Cell::new(0);
+      let result = expr(&mut advancer, &mut memo, &calls);
+      (result, advancer.pos(), calls.get())
+   }
+
+   #[test]
+   fn test_memo_result() {
+      assert_eq!(parse("x+x-(x+x)", Memo::new()).0, Some(4));
+      assert_eq!(parse("x+x-(x+x)", Memo::disabled()).0, Some(4));
+      assert_eq!(parse("(x", Memo::new()), (None, 0, 4));
+      assert_eq!(parse("x+", Memo::new()), (Some(1), 1, 4));
+   }
+
+   #[test]
+   fn test_memo_linear() {
+      let nested = format!("{}x{}", "(".repeat(8), ")".repeat(8));
+      let (result, end, memo_calls) = parse(&nested, Memo::new());
+      assert_eq!((result, end), (Some(1), nested.len()));
+      let (result, end, plain_calls) = parse(&nested, Memo::disabled());
+      assert_eq!((result, end), (Some(1), nested.len()));
+      assert_eq!(memo_calls, 18);
+      assert!(plain_calls > 10_000);
+   }
+}
@@ -1,1254 +1,2181 @@
-use tokenize::{Syn, Token};
-
-const INDENT: usize = 3;
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-enum Node {
-   Fn,
-   FnCall,
-   FnArgs,
-   Block,
-   Ret,
-   RetList,
-   Assign,
-   AddAssign,
-   SubtractAssign,
-   MultiplyAssign,
-   DivideAssign,
-   Equal,
-   Unequal,
-   LessEqual,
-   GreaterEqual,
-   Add,
-   Subtract,
-   Multiply,
-   Divide,
-   Range,
-   Match,
-   MatchArm,
-   MatchBody,
-   Map,
-   MapItem,
-   And,
-   Or,
-   Not,
-   If,
-   El,
-   For,
-   Loop,
-   List,
-   Ident,
-   Number,
-   Symbol,
-   String,
-   Break,
-   Parens,
-   Pattern,
+use ast::{
+   AssignOp, BinaryOp, Block, ElseIf, Expr, ExprKind, FnDef, Ident, MapEntry, MatchArm, Module,
+   Pattern, PatternEntry, PatternKind, Span, Stmt, StmtKind,
+};
+use indentation::module_indentation;
+use scope::captures;
+use std::mem;
+use tokenize::{tok_advancer, tokenize, Tok, TokAdvancer, TokMeta};
+use trace::Trace;
+
+/// Indentation unit assumed when no line of the module is indented.
+const DEFAULT_INDENTATION: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+   pub tok: usize,
+   pub line: usize,
+   pub col: usize,
 }
 
-type Res = Result<Option<(usize, Node)>, usize>;
-
-fn no() -> Res {
-   Ok(None)
+/// Module parsed as far as possible, with statements that failed to parse
+/// replaced by `StmtKind::Error`, and an error for each of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed {
+   pub module: Module,
+   pub errors: Vec<SyntaxError>,
 }
 
-fn ok(pos: usize, ast: Node) -> Res {
-   Ok(Some((pos, ast)))
+/// A rule either matches, does not apply at the current position, or fails
+/// at the position of the offending token.
+type Res<T> = Result<Option<T>, usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assoc {
+   Left,
+   Right,
+   None,
 }
 
-pub struct Parser<'a, 'b> {
-   tokens: &'a [Token],
-   input: &'b str,
+/// Binary operators with their precedence, a higher one binding tighter:
+///
+/// | Precedence | Operators                    | Associativity |
+/// |------------|------------------------------|---------------|
+/// | 1          | `or`                         | left          |
+/// | 2          | `and`                        | left          |
+/// | 3          | `not` (prefix)               |               |
+/// | 4          | `==` `!=` `<` `>` `<=` `>=`  | none          |
+/// | 5          | `..`                         | none          |
+/// | 6          | `+` `-`                      | left          |
+/// | 7          | `*` `/`                      | left          |
+/// | 8          | `-` `+` (prefix)             |               |
+/// | 9          | `**`                         | right         |
+///
+/// Operators without associativity cannot be chained, so `a < b < c` and
+/// `a == b != c` are errors rather than silently grouped. A sign directly
+/// before a number is part of the literal, unless `**` follows, so `-2 * 3`
+/// multiplies `-2`, while `-2 ** 2` is `-(2 ** 2)`.
+const BINARY: &[(Tok, BinaryOp, u8, Assoc)] = &[
+   (Tok::Or, BinaryOp::Or, 1, Assoc::Left),
+   (Tok::And, BinaryOp::And, 2, Assoc::Left),
+   (Tok::DoubleEquals, BinaryOp::Equal, 4, Assoc::None),
+   (Tok::ExclamationEquals, BinaryOp::Unequal, 4, Assoc::None),
+   (Tok::LessThan, BinaryOp::Less, 4, Assoc::None),
+   (Tok::GreaterThan, BinaryOp::Greater, 4, Assoc::None),
+   (Tok::LessThanEquals, BinaryOp::LessEqual, 4, Assoc::None),
+   (
+      Tok::GreaterThanEquals,
+      BinaryOp::GreaterEqual,
+      4,
+      Assoc::None,
+   ),
+   (Tok::DoubleFullStop, BinaryOp::Range, 5, Assoc::None),
+   (Tok::Plus, BinaryOp::Add, 6, Assoc::Left),
+   (Tok::Minus, BinaryOp::Subtract, 6, Assoc::Left),
+   (Tok::Asterisk, BinaryOp::Multiply, 7, Assoc::Left),
+   (Tok::Slash, BinaryOp::Divide, 7, Assoc::Left),
+   (Tok::DoubleAsterisk, BinaryOp::Power, 9, Assoc::Right),
+];
+
+const NOT_PRECEDENCE: u8 = 3;
+
+const SIGN_PRECEDENCE: u8 = 8;
+
+const ASSIGN: &[(Tok, AssignOp)] = &[
+   (Tok::Equals, AssignOp::Assign),
+   (Tok::PlusEquals, AssignOp::Add),
+   (Tok::MinusEquals, AssignOp::Subtract),
+   (Tok::AsteriskEquals, AssignOp::Multiply),
+   (Tok::SlashEquals, AssignOp::Divide),
+];
+
+pub struct Parser<'t> {
+   chars: &'t [char],
+   toks: &'t [Tok],
+   toks_meta: &'t [TokMeta],
+   advancer: TokAdvancer<'t>,
+   indentation: usize,
+   indent: usize,
+   errors: Vec<SyntaxError>,
+   trace: Option<&'t mut dyn Trace>,
 }
 
-impl<'a, 'b> Parser<'a, 'b> {
-   pub fn init(tokens: &'a [Token], input: &'b str) -> Self {
+impl<'t> Parser<'t> {
+   pub fn new(
+      chars: &'t [char],
+      toks: &'t [Tok],
+      toks_meta: &'t [TokMeta],
+      line_starts: &[usize],
+   ) -> Self {
+      let indentation =
+         match module_indentation(chars, toks, toks_meta, line_starts, None).indentation {
+            0 => DEFAULT_INDENTATION,
+            indentation => indentation,
+         };
+
       Parser {
-         tokens: tokens,
-         input: input,
+         chars,
+         toks,
+         toks_meta,
+         advancer: tok_advancer(toks),
+         indentation,
+         indent: 0,
+         errors: Vec::new(),
+         trace: None,
       }
    }
 
-   pub fn parse(&self) {
-      let mut current = 0;
-
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      }
-
-      match self.block(current, 0) {
-         Err(pos) => {
-            self.error(pos);
-            return;
-         },
-         Ok(Some((pos, ast))) => {
-            current = pos;
-            println!("[{}] done {:?}", current, ast);
-         }
-         Ok(None) => {
-            println!("Unreachable");
-            self.error(current);
-            return;
-         }
-      }
+   /// Reports the rules and tokens the parser goes through to `trace`.
+   pub fn with_trace(mut self, trace: &'t mut dyn Trace) -> Self {
+      self.trace = Some(trace);
+      self
    }
 
-   fn error(&self, pos: usize) {
-      let token = &self.tokens[pos];
-      let error = format!(
-         "[{}] syntax error at line: {}, col: {}",
-         pos, token.line, token.col
-      );
-      println!("{}", &error);
-      println!("{}", "=".repeat(error.len()));
-
-      let start = pos - ::std::cmp::min(pos, 5);
-      let offset = ::std::cmp::min(11, self.tokens.len() - start);
-      let slice = &self.tokens[start..start + offset];
+   pub fn parse(&mut self) -> Parsed {
+      let module = self.module();
 
-      for (i, token) in slice.iter().enumerate() {
-         let name = &self.input[token.pos..token.pos + token.span];
-         println!("[{}] {:?} {:?}", start+i, token.syn, name);
+      Parsed {
+         module,
+         errors: mem::take(&mut self.errors),
       }
    }
 
-   fn block(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
-      let mut last = current;
-      let mut consumed = false;
-
-      loop {
-         if let Some((pos, _ast)) = self.statement(current, indent)? {
-            current = pos;
-            consumed = true;
-            println!("[{}-{}] statement", last, current - 1);
-            last = current;
-         } else {
-            if consumed {
-               println!("[{}-{}] block ...", pos, current - 1);
-               return ok(current, Node::Block);
-            } else {
-               return Err(current);
-            }
-         }
-      }
+   /// Indentation unit of the module, in spaces.
+   pub fn indentation(&self) -> usize {
+      self.indentation
    }
 
-   fn statement(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
-
-      if let Some(pos) = self.indent_space(current, indent) {
-         current = pos;
-      } else {
-         return no();
+   /// Block `indent` levels deep whose first line starts at token `start`,
+   /// for parsing an edited block again, with the position after it and
+   /// the errors within it. `None` when no statement starts there.
+   pub fn parse_block(
+      &mut self,
+      start: usize,
+      indent: usize,
+   ) -> Option<(Block, usize, Vec<SyntaxError>)> {
+      if start > 0 {
+         self.advancer.advance(start);
       }
+      self.indent = indent;
+      self.line_ends();
 
-      let ast = if let Some((pos, ast)) = self.fn_(current, indent)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.for_(current, indent)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.loop_(current, indent)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.break_(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.ret(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.assign(current, indent)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.resulting(current, indent)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.ret_list(current)? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+      let block = self.block().ok()?;
 
-      ok(current, ast)
+      Some((block, self.advancer.pos(), mem::take(&mut self.errors)))
    }
 
-   fn fn_(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+   /// Errors at the end of the input are reported right after the last
+   /// token.
+   fn syntax_error(&self, pos: usize) -> SyntaxError {
+      let (line, col) = match (self.toks_meta.get(pos), self.toks_meta.last()) {
+         (Some(meta), _) => (meta.line, meta.col),
+         (None, Some(meta)) if self.toks.last() == Some(&Tok::LineEnd) => (meta.line + 1, 1),
+         (None, Some(meta)) => (meta.line, meta.col + meta.span),
+         (None, None) => (1, 1),
+      };
 
-      if let Some(pos) = self.token_type(current, Syn::Fn) {
-         current = pos;
-      } else {
-         return no();
+      SyntaxError {
+         tok: pos,
+         line,
+         col,
       }
+   }
 
-      current = self.skip_space(current);
+   fn module(&mut self) -> Module {
+      self.line_ends();
 
-      if let Some((pos, _ast)) = self.ident(current)? {
-         current = pos;
+      let body = if self.advancer.cannot_peek() {
+         Vec::new()
       } else {
-         println!("[{}] expected function name", current);
-         return Err(current);
+         match self.block() {
+            Ok(block) => block.stmts,
+            Err(pos) => {
+               let error = self.syntax_error(pos);
+               self.errors.push(error);
+               Vec::new()
+            }
+         }
       };
 
-      current = self.skip_space(current);
-
-      if let Some(pos) = self.token_type(current, Syn::ParenLeft) {
-         current = pos;
-      } else {
-         println!("[{}] expected (", current);
-         return Err(current);
+      Module {
+         body,
+         span: Span::new(0, self.toks.len()),
       }
+   }
 
-      if let Some((pos, _args)) = self.fn_args(current)? {
-         current = pos;
-      }
+   /// Statements at the current indentation. A statement that fails to
+   /// parse is recorded as an error and skipped, as are lines indented
+   /// deeper than the block without belonging to a statement.
+   fn block(&mut self) -> Result<Block, usize> {
+      let mut stmts = Vec::new();
 
-      if let Some(pos) = self.token_type(current, Syn::ParenRight) {
-         current = pos;
-      } else {
-         println!("[{}] expected )", current);
-         return Err(current);
+      loop {
+         let start = self.advancer.pos();
+
+         match self.statement() {
+            Ok(Some(stmt)) => stmts.push(stmt),
+            Ok(None) if self.deeper() => {
+               let pos = self.advancer.next_pos();
+               stmts.push(self.recover(start, pos));
+            }
+            Ok(None) => break,
+            Err(pos) => stmts.push(self.recover(start, pos)),
+         }
       }
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      } else {
-         println!("[{}] expected new line on fn", current);
-         return Err(current);
+      match (stmts.first(), stmts.last()) {
+         (Some(first), Some(last)) => {
+            let span = first.span.to(last.span);
+            Ok(Block { stmts, span })
+         }
+         _ => Err(self.advancer.next_pos()),
       }
+   }
 
-      println!("[{}] fn ()", pos);
+   /// Records the error at `pos` and skips the statement starting at
+   /// `start`: the rest of its line, continuing over line ends inside
+   /// brackets that are still open, and the lines indented deeper than it
+   /// that follow.
+   fn recover(&mut self, start: usize, pos: usize) -> Stmt {
+      let first = self.skip_failed(start, pos);
 
-      if let Some((pos, _ast)) = self.block(current, indent + 1)? {
-         current = pos;
-      } else {
-         println!("[{}] expected fn block", current);
-         return Err(current);
+      Stmt {
+         kind: StmtKind::Error,
+         span: self.statement_span(first),
       }
-
-      ok(current, Node::Fn)
    }
 
-   fn fn_args(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// Records the error at `pos` and skips the statement or entry starting
+   /// at `start` as `recover` describes, returning its first token.
+   fn skip_failed(&mut self, start: usize, pos: usize) -> usize {
+      let error = self.syntax_error(pos);
+      self.errors.push(error);
 
-      current = self.skip_white_space(current);
+      self.advancer.seek(start);
+      let first = self.advancer.next_pos();
 
-      loop {
-         if let Some((pos, _ast)) = self.ident(current)? {
-            current = pos;
+      let mut depth = 0isize;
+      let mut end = start;
 
-            current = self.skip_white_space(current)
-         } else {
-            return ok(current, Node::FnArgs);
+      while let Some(tok) = self.toks.get(end) {
+         match *tok {
+            Tok::ParenLeft | Tok::SquareBracketLeft | Tok::CurlyBracketLeft => depth += 1,
+            Tok::ParenRight | Tok::SquareBracketRight | Tok::CurlyBracketRight => depth -= 1,
+            Tok::LineEnd if depth <= 0 || !self.inside_brackets(end + 1) => break,
+            _ => {}
          }
+         end += 1;
       }
-   }
 
-   fn loop_(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+      self.advancer.seek(end);
+      self.line_ends();
 
-      if let Some(pos) = self.token_type(current, Syn::Loop) {
-         current = pos;
-      } else {
-         return no();
+      while self.deeper() {
+         self.skip_line();
       }
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      } else {
-         println!("[{}] expected new line on loop", current);
-         return Err(current);
+      first
+   }
+
+   /// Whether the line starting at `pos` still belongs to an open bracket,
+   /// being blank, indented deeper than the current level or closing it.
+   fn inside_brackets(&self, pos: usize) -> bool {
+      let (spaces, first) = match self.toks.get(pos) {
+         Some(&Tok::Space) => (self.toks_meta[pos].span, self.toks.get(pos + 1)),
+         first => (0, first),
+      };
+
+      match first {
+         None => false,
+         Some(&Tok::LineEnd)
+         | Some(&Tok::ParenRight)
+         | Some(&Tok::SquareBracketRight)
+         | Some(&Tok::CurlyBracketRight) => true,
+         Some(_) => spaces > self.indent * self.indentation,
       }
+   }
 
-      println!("[{}-{}] loop", pos, current - 1);
+   fn skip_line(&mut self) {
+      let mut end = self.advancer.pos();
 
-      if let Some((pos, _ast)) = self.block(current, indent + 1)? {
-         current = pos;
-      } else {
-         println!("[{}] expected loop block", current);
-         return Err(current);
+      while end < self.toks.len() && self.toks[end] != Tok::LineEnd {
+         end += 1;
       }
 
-      ok(current, Node::Loop)
+      self.advancer.seek(end);
+      self.line_ends();
+   }
+
+   fn indented_block(&mut self) -> Result<Block, usize> {
+      self.indent += 1;
+      let block = self.block();
+      self.indent -= 1;
+      block
    }
 
-   fn if_(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+   fn statement(&mut self) -> Res<Stmt> {
+      self.traced("statement", |parser| {
+         if parser.advancer.cannot_peek() || !parser.indented(parser.indent) {
+            return Ok(None);
+         }
 
-      if let Some(pos) = self.token_type(current, Syn::If) {
-         current = pos;
-      } else {
-         return no();
-      }
+         let start = parser.advancer.next_pos();
+
+         let kind = if let Some(kind) = parser.fn_()? {
+            kind
+         } else if let Some(kind) = parser.for_()? {
+            kind
+         } else if let Some(kind) = parser.loop_()? {
+            kind
+         } else if let Some(kind) = parser.break_()? {
+            kind
+         } else if let Some(kind) = parser.ret()? {
+            kind
+         } else if let Some(kind) = parser.assign()? {
+            kind
+         } else if let Some(expr) = parser.resulting()? {
+            StmtKind::Expr(expr)
+         } else {
+            return Err(start);
+         };
 
-      current = self.skip_space(current);
+         let span = match kind {
+            StmtKind::Fn(ref def) => def.span,
+            StmtKind::For { ref body, .. } | StmtKind::Loop(ref body) => {
+               Span::new(start, body.span.end)
+            }
+            StmtKind::Assign { ref value, .. } | StmtKind::Expr(ref value) => {
+               Span::new(start, value.span.end)
+            }
+            StmtKind::Break | StmtKind::Ret(_) | StmtKind::Error => parser.statement_span(start),
+         };
 
-      let ast = if let Some((pos, ast)) = self.end_expression(current)? {
-         current = pos;
-         ast
-      } else {
-         return Err(current);
-      };
+         Ok(Some(Stmt { kind, span }))
+      })
+   }
 
-      println!("[{}] if {:?}", pos, ast);
+   fn fn_(&mut self) -> Res<StmtKind> {
+      self.traced("fn", |parser| {
+         let start = parser.advancer.next_pos();
 
-      if let Some((pos, _ast)) = self.block(current, indent + 1)? {
-         current = pos;
-      } else {
-         println!("[{}] expected if block", current);
-         return Err(current);
-      }
+         if !parser.tok(Tok::Fn) {
+            return Ok(None);
+         }
 
-      if let Some((pos, _ast)) = self.el(current, indent)? {
-         current = pos;
-      }
+         let name = parser.ident().ok_or_else(|| parser.advancer.next_pos())?;
 
-      println!("[{}-{}] if ...", pos, current - 1);
+         parser.expect(Tok::ParenLeft)?;
 
-      ok(current, Node::If)
+         let mut params = Vec::new();
+         while let Some(param) = parser.ident() {
+            params.push(param);
+         }
+
+         parser.expect(Tok::ParenRight)?;
+         parser.expect_line_ends()?;
+
+         let body = parser.indented_block()?;
+         let span = Span::new(start, body.span.end);
+
+         Ok(Some(StmtKind::Fn(FnDef {
+            name,
+            params,
+            body,
+            span,
+         })))
+      })
    }
 
-   fn el(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+   fn for_(&mut self) -> Res<StmtKind> {
+      self.traced("for", |parser| {
+         if !parser.tok(Tok::For) {
+            return Ok(None);
+         }
 
-      if let Some(pos) = self.indent_space(current, indent) {
-         current = pos;
-      } else {
-         return no();
-      }
+         let pattern = parser.required(Self::pattern)?;
+         parser.expect(Tok::In)?;
+         let iterable = parser.required(Self::expression)?;
+         parser.expect_line_ends()?;
+         let body = parser.indented_block()?;
+
+         Ok(Some(StmtKind::For {
+            pattern,
+            iterable,
+            body,
+         }))
+      })
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::El) {
-         current = pos;
-      } else {
-         return no();
-      }
+   fn loop_(&mut self) -> Res<StmtKind> {
+      self.traced("loop", |parser| {
+         if !parser.tok(Tok::Loop) {
+            return Ok(None);
+         }
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      } else {
-         println!("[{}] expected new line on el", current);
-         return Err(current);
-      }
+         parser.expect_line_ends()?;
 
-      println!("[{}-{}] el", pos, current - 1);
+         Ok(Some(StmtKind::Loop(parser.indented_block()?)))
+      })
+   }
 
-      if let Some((pos, _ast)) = self.block(current, indent + 1)? {
-         current = pos;
-      } else {
-         println!("[{}] expected loop block", current);
-         return Err(current);
-      }
+   fn break_(&mut self) -> Res<StmtKind> {
+      self.traced("break", |parser| {
+         if !parser.tok(Tok::Break) {
+            return Ok(None);
+         }
 
-      ok(current, Node::El)
+         parser.expect_line_ends()?;
+
+         Ok(Some(StmtKind::Break))
+      })
    }
 
-   fn match_(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+   fn ret(&mut self) -> Res<StmtKind> {
+      self.traced("ret", |parser| {
+         if !parser.tok(Tok::Ret) {
+            return Ok(None);
+         }
 
-      if let Some(pos) = self.token_type(current, Syn::Match) {
-         current = pos;
-      } else {
-         return no();
-      }
+         let values = parser.values()?;
+         parser.expect_line_ends()?;
 
-      current = self.skip_space(current);
+         Ok(Some(StmtKind::Ret(values)))
+      })
+   }
 
-      if let Some((pos, _ast)) = self.end_expression(current)? {
-         current = pos;
-      } else {
-         return Err(current);
-      }
+   fn assign(&mut self) -> Res<StmtKind> {
+      self.traced("assign", |parser| {
+         let pos = parser.advancer.pos();
+
+         // Whatever does not parse as a pattern followed by an assignment
+         // operator is left for the expression statement.
+         let target = match parser.pattern() {
+            Ok(Some(target)) => target,
+            _ => {
+               parser.advancer.seek(pos);
+               return Ok(None);
+            }
+         };
 
-      println!("[{}-{}] match", pos, current - 1);
+         let op_pos = parser.advancer.next_pos();
 
-      if let Some((pos, _ast)) = self.match_arms(current, indent + 1)? {
-         current = pos;
-      } else {
-         return Err(current);
-      }
+         let op = match parser.operator(ASSIGN) {
+            Some(op) => op,
+            None => {
+               parser.advancer.seek(pos);
+               return Ok(None);
+            }
+         };
 
-      ok(current, Node::Match)
-   }
+         match (op, &target.kind) {
+            (AssignOp::Assign, _) | (_, &PatternKind::Ident(_)) => {}
+            _ => return Err(op_pos),
+         }
 
-   fn match_arms(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
-      let mut consumed = false;
+         let value = parser.required(Self::resulting)?;
 
-      loop {
-         if let Some((pos, _ast)) = self.match_arm(current, indent)? {
-            current = pos;
-            consumed = true;
+         Ok(Some(StmtKind::Assign { target, op, value }))
+      })
+   }
+
+   /// Expressions that can stand on their own line, including the block
+   /// forms, consuming the line end that follows.
+   fn resulting(&mut self) -> Res<Expr> {
+      self.traced("resulting", |parser| {
+         if let Some(expr) = parser.if_()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.match_()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.map_block()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.lambda_block()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.values()? {
+            parser.expect_line_ends()?;
+            Ok(Some(expr))
          } else {
-            if consumed {
-               return ok(current, Node::MatchBody);
-            } else {
-               println!("[{}] expected match arms", current);
-               return Err(current);
-            }
+            Ok(None)
          }
-      }
+      })
    }
 
-   fn match_arm(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+   fn if_(&mut self) -> Res<Expr> {
+      self.traced("if", |parser| {
+         let start = parser.advancer.next_pos();
 
-      if let Some(pos) = self.indent_space(current, indent) {
-         current = pos;
-      } else {
-         return no();
-      }
+         if !parser.tok(Tok::If) {
+            return Ok(None);
+         }
 
-      if let Some((pos, _ast)) = self.pattern(current)? {
-         current = pos;
-      } else {
-         println!("[{}] expected pattern", current);
-         return Err(current);
-      }
+         let condition = parser.required(Self::expression)?;
+         parser.expect_line_ends()?;
+         let then = parser.indented_block()?;
 
-      current = self.skip_space(current);
+         let mut efs = Vec::new();
 
-      if let Some(pos) = self.token_type(current, Syn::Colon) {
-         current = pos;
-      } else {
-         println!("[{}] expected :", current);
-         return Err(current);
-      }
+         loop {
+            let pos = parser.advancer.pos();
+            let ef_start = parser.advancer.next_pos();
 
-      println!("[{}-{}] pattern: ...", pos, current - 1);
+            if !parser.indented(parser.indent) || !parser.tok(Tok::Ef) {
+               parser.advancer.seek(pos);
+               break;
+            }
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
+            let condition = parser.required(Self::expression)?;
+            parser.expect_line_ends()?;
+            let then = parser.indented_block()?;
+            let span = Span::new(ef_start, then.span.end);
 
-         if let Some((pos, _ast)) = self.block(current, indent + 1)? {
-            current = pos;
-         } else {
-            println!("[{}] expected fn block", current);
-            return Err(current);
+            efs.push(ElseIf {
+               condition,
+               then,
+               span,
+            });
          }
-      } else {
-         current = self.skip_space(current);
 
-         if let Ok(Some((pos, _))) = self.resulting(current, indent) {
-            current = pos;
+         let pos = parser.advancer.pos();
+
+         let el = if parser.indented(parser.indent) && parser.tok(Tok::El) {
+            parser.expect_line_ends()?;
+            Some(parser.indented_block()?)
          } else {
-            println!("[{}] expected expression", current);
-            return Err(current);
+            parser.advancer.seek(pos);
+            None
+         };
+
+         let end = match el {
+            Some(ref el) => el.span.end,
+            None => efs.last().map_or(then.span.end, |ef| ef.span.end),
+         };
+
+         Ok(Some(Expr {
+            kind: ExprKind::If {
+               condition: Box::new(condition),
+               then,
+               efs,
+               el,
+            },
+            span: Span::new(start, end),
+         }))
+      })
+   }
+
+   fn match_(&mut self) -> Res<Expr> {
+      self.traced("match", |parser| {
+         let start = parser.advancer.next_pos();
+
+         if !parser.tok(Tok::Match) {
+            return Ok(None);
          }
-      }
 
-      ok(current, Node::MatchArm)
+         let subject = parser.required(Self::expression)?;
+         parser.expect_line_ends()?;
+
+         let arms = parser.indented_entries(Self::match_arm)?;
+         let span = Span::new(start, arms[arms.len() - 1].span.end);
+
+         Ok(Some(Expr {
+            kind: ExprKind::Match {
+               subject: Box::new(subject),
+               arms,
+            },
+            span,
+         }))
+      })
    }
 
-   fn for_(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+   fn match_arm(&mut self) -> Res<MatchArm> {
+      self.traced("match arm", |parser| {
+         let pattern = match parser.pattern()? {
+            Some(pattern) => pattern,
+            None => return Ok(None),
+         };
+
+         parser.expect(Tok::Colon)?;
+         let body = parser.entry_value()?;
+         let span = pattern.span.to(body.span);
+
+         Ok(Some(MatchArm {
+            pattern,
+            body,
+            span,
+         }))
+      })
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::For) {
-         current = pos;
-      } else {
-         return no();
-      }
+   /// Map spread over indented lines, introduced by the contextual `map`
+   /// keyword at the end of a line. Without indented lines below, `map` is
+   /// an ordinary name.
+   fn map_block(&mut self) -> Res<Expr> {
+      self.traced("map block", |parser| {
+         let pos = parser.advancer.pos();
+         let start = parser.advancer.next_pos();
+
+         if !parser.tok(Tok::Identifier)
+            || parser.text(start) != "map"
+            || !parser.line_ends()
+            || !parser.deeper()
+         {
+            parser.advancer.seek(pos);
+            return Ok(None);
+         }
 
-      current = self.skip_space(current);
+         let entries = parser.indented_entries(Self::map_block_entry)?;
+         let span = Span::new(start, entries[entries.len() - 1].span.end);
 
-      if let Some((pos, _ast)) = self.pattern(current)? {
-         current = pos;
-      } else {
-         println!("[{}] expected pattern", current);
-         return Err(current);
-      }
+         Ok(Some(Expr {
+            kind: ExprKind::Map(entries),
+            span,
+         }))
+      })
+   }
+
+   fn map_block_entry(&mut self) -> Res<MapEntry> {
+      self.traced("map entry", |parser| {
+         let key = match parser.expression()? {
+            Some(key) => key,
+            None => return Ok(None),
+         };
+
+         parser.expect(Tok::Colon)?;
+         let value = parser.entry_value()?;
+         let span = key.span.to(value.span);
 
-      current = self.skip_space(current);
+         Ok(Some(MapEntry { key, value, span }))
+      })
+   }
+
+   /// Value after the colon of a match arm or map entry, either inline or
+   /// as an indented block on the following lines.
+   fn entry_value(&mut self) -> Result<Expr, usize> {
+      if self.line_ends() {
+         let block = self.indented_block()?;
+         let span = block.span;
 
-      if let Some(pos) = self.token_type(current, Syn::In) {
-         current = pos;
+         Ok(Expr {
+            kind: ExprKind::Block(block),
+            span,
+         })
       } else {
-         println!("[{}] expected in", current);
-         return Err(current);
+         self.required(Self::resulting)
       }
+   }
 
-      current = self.skip_space(current);
+   /// One or more lines one level deeper than the current indentation,
+   /// each parsed by `entry`. An entry that fails is skipped like a
+   /// statement, keeping the others. When none is left, the error of the
+   /// first one is returned instead.
+   fn indented_entries<T>(&mut self, entry: fn(&mut Self) -> Res<T>) -> Result<Vec<T>, usize> {
+      let mut entries = Vec::new();
+      let mut failed = None;
+      let errors = self.errors.len();
+
+      self.indent += 1;
+
+      while !self.advancer.cannot_peek() && self.indented(self.indent) {
+         let start = self.advancer.pos();
+
+         match self.required(entry) {
+            Ok(parsed) => entries.push(parsed),
+            Err(pos) => {
+               failed = failed.or(Some(pos));
+               self.skip_failed(start, pos);
+            }
+         }
+      }
 
-      let _ast = if let Some((pos, ast)) = self.end_expression(current)? {
-         current = pos;
-         ast
-      } else {
-         return Err(current);
-      };
+      self.indent -= 1;
 
-      if let Some((pos, _ast)) = self.block(current, indent + 1)? {
-         current = pos;
-      } else {
-         println!("[{}] expected for block", current);
-         return Err(current);
+      if entries.is_empty() {
+         self.errors.truncate(errors);
+         return Err(failed.unwrap_or_else(|| self.advancer.next_pos()));
       }
 
-      ok(current, Node::For)
+      Ok(entries)
    }
 
-   fn ret(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// One pattern, or space separated patterns matching multiple values.
+   fn pattern(&mut self) -> Res<Pattern> {
+      self.traced("pattern", |parser| {
+         let mut patterns = Vec::new();
 
-      if let Some(pos) = self.token_type(current, Syn::Ret) {
-         current = pos;
-      } else {
-         return no();
-      }
+         while let Some(pattern) = parser.pattern_element()? {
+            patterns.push(pattern);
+         }
 
-      current = self.skip_space(current);
+         if patterns.len() > 1 {
+            let span = patterns[0].span.to(patterns[patterns.len() - 1].span);
 
-      let _ast = if let Some((pos, ast)) = self.single_line_list_items(current)? {
-         current = pos;
-         ast
-      } else {
-         return Err(current);
-      };
+            Ok(Some(Pattern {
+               kind: PatternKind::Multi(patterns),
+               span,
+            }))
+         } else {
+            Ok(patterns.pop())
+         }
+      })
+   }
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      } else {
-         println!("[{}] expected new line on ret", current);
-         return Err(current);
-      }
+   fn pattern_element(&mut self) -> Res<Pattern> {
+      self.traced("pattern element", |parser| {
+         let start = parser.advancer.next_pos();
 
-      ok(current, Node::Ret)
+         let kind = if let Some(ident) = parser.ident() {
+            if ident.name == "_" {
+               PatternKind::Wildcard
+            } else {
+               PatternKind::Ident(ident.name)
+            }
+         } else if let Some(value) = parser.value()? {
+            if parser.tok(Tok::DoubleFullStop) {
+               PatternKind::Range {
+                  start: value,
+                  end: parser.required(Self::value)?,
+               }
+            } else {
+               PatternKind::Value(value)
+            }
+         } else if parser.tok(Tok::ParenLeft) {
+            let mut patterns = parser.pattern_items()?;
+            parser.expect(Tok::ParenRight)?;
+
+            if patterns.len() == 1 {
+               let mut pattern = patterns.remove(0);
+               pattern.span = parser.span(start);
+               return Ok(Some(pattern));
+            }
+
+            PatternKind::List(patterns)
+         } else if parser.tok(Tok::SquareBracketLeft) {
+            let patterns = parser.pattern_items()?;
+            parser.expect(Tok::SquareBracketRight)?;
+            PatternKind::List(patterns)
+         } else if parser.tok(Tok::CurlyBracketLeft) {
+            parser.line_ends();
+
+            let mut entries = Vec::new();
+
+            while let Some(key) = parser.value()? {
+               parser.expect(Tok::Colon)?;
+               let value = parser.required(Self::pattern_element)?;
+               let span = key.span.to(value.span);
+               entries.push(PatternEntry { key, value, span });
+               parser.line_ends();
+            }
+
+            parser.expect(Tok::CurlyBracketRight)?;
+            PatternKind::Map(entries)
+         } else {
+            return Ok(None);
+         };
+
+         Ok(Some(Pattern {
+            kind,
+            span: parser.span(start),
+         }))
+      })
    }
 
-   fn ret_list(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// Patterns inside brackets, where line ends carry no meaning.
+   fn pattern_items(&mut self) -> Result<Vec<Pattern>, usize> {
+      let mut items = Vec::new();
 
-      let _ast = if let Some((pos, ast)) = self.ret_list_items(current)? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+      self.line_ends();
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      } else {
-         println!("[{}] expected new line on ret list", current);
-         return Err(current);
+      while let Some(pattern) = self.pattern_element()? {
+         items.push(pattern);
+         self.line_ends();
       }
 
-      ok(current, Node::RetList)
+      Ok(items)
    }
 
-   fn ret_list_items(&self, pos: usize) -> Res {
-      let mut current = pos;
-      let mut consumed = 0;
+   /// One expression, or a list of space separated expressions.
+   fn values(&mut self) -> Res<Expr> {
+      self.traced("values", |parser| {
+         let mut values = Vec::new();
 
-      current = self.skip_space(current);
+         while let Some(expr) = parser.expression()? {
+            values.push(expr);
+         }
 
-      loop {
-         if let Some((pos, _ast)) = self.expression(current)? {
-            current = pos;
-            consumed += 1;
+         if values.len() > 1 {
+            let span = values[0].span.to(values[values.len() - 1].span);
 
-            current = self.skip_space(current)
+            Ok(Some(Expr {
+               kind: ExprKind::List(values),
+               span,
+            }))
          } else {
-            if consumed >= 2 {
-               return ok(current, Node::RetList);
-            } else {
-               return no()
-            }
+            Ok(values.pop())
          }
-      }
+      })
    }
 
-   fn assign(&self, pos: usize, indent: usize) -> Res {
-      let mut current = pos;
+   fn expression(&mut self) -> Res<Expr> {
+      self.traced("expression", |parser| parser.binary(0))
+   }
 
-      if let Some((pos, _ast)) = self.ident(current)? {
-         current = pos;
-      } else {
-         return no();
+   /// Precedence climbing over `BINARY`, parsing only the operators that
+   /// bind at least as tight as `min`.
+   fn binary(&mut self, min: u8) -> Res<Expr> {
+      let mut left = match self.unary()? {
+         Some(left) => left,
+         None => return Ok(None),
       };
 
-      current = self.skip_space(current);
-
-      let ast = if let Some((pos, ast)) = self.assign_type(
-         current, indent, Syn::Assign, Node::Assign
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.assign_type(
-         current, indent, Syn::AddAssign, Node::AddAssign
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.assign_type(
-         current, indent, Syn::SubtractAssign, Node::SubtractAssign
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.assign_type(
-         current, indent, Syn::MultiplyAssign, Node::MultiplyAssign
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.assign_type(
-         current, indent, Syn::DivideAssign, Node::DivideAssign
-      )? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+      let mut chained = None;
 
-      println!("[{}-{}] {:?}", pos, current - 1, ast);
+      loop {
+         let pos = self.advancer.pos();
 
-      ok(current, ast)
-   }
+         self.continuation();
 
-   fn assign_type(&self, pos: usize, indent: usize, syn: Syn, node: Node) -> Res {
-      let mut current = pos;
+         let (op, precedence, assoc) = match self.binary_operator() {
+            Some(operator) if operator.1 >= min => operator,
+            _ => {
+               self.advancer.seek(pos);
+               return Ok(Some(left));
+            }
+         };
 
-      if let Some(pos) = self.token_type(current, syn) {
-         current = pos;
-      } else {
-         return no();
-      }
+         if chained == Some(precedence) {
+            return Err(self.advancer.next_pos());
+         }
 
-      current = self.skip_space(current);
+         self.take();
 
-      if let Some((pos, _ast)) = self.resulting(current, indent)? {
-         current = pos;
-      } else {
-         println!("[{}] expected expression", current);
-         return Err(current);
-      }
+         let right_min = match assoc {
+            Assoc::Right => precedence,
+            Assoc::Left | Assoc::None => precedence + 1,
+         };
 
-      ok(current, node)
-   }
-
-   fn binary_right(&self, pos: usize) -> Res {
-      let mut current = pos;
-
-      let ast = if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Equal, Node::Equal
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Unequal, Node::Unequal
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::LessEqual, Node::LessEqual
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::GreaterEqual, Node::GreaterEqual
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Add, Node::Add
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Subtract, Node::Subtract
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Multiply, Node::Multiply
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Divide, Node::Divide
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Or, Node::Or
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::And, Node::And
-      )? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Range, Node::Range
-      )? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+         let right = self.required(|parser| parser.binary(right_min))?;
 
-      current = self.skip_space(current);
+         chained = match assoc {
+            Assoc::None => Some(precedence),
+            Assoc::Left | Assoc::Right => None,
+         };
 
-      if let Some((pos, _ast)) = self.expression(current)? {
-         current = pos;
-      } else {
-         println!("[{}] expected expression", current);
-         return Err(current);
+         let span = left.span.to(right.span);
+
+         left = Expr {
+            kind: ExprKind::Binary {
+               op,
+               left: Box::new(left),
+               right: Box::new(right),
+            },
+            span,
+         };
+      }
+   }
+
+   fn binary_operator(&self) -> Option<(BinaryOp, u8, Assoc)> {
+      if self.signed_number_ahead() {
+         return None;
       }
 
-      ok(current, ast)
+      let tok = self.advancer.peek_nth(0)?;
+
+      BINARY
+         .iter()
+         .find(|operator| &operator.0 == tok)
+         .map(|&(_, op, precedence, assoc)| (op, precedence, assoc))
    }
 
-   fn unary(&self, pos: usize) -> Res {
-      let mut current = pos;
+   fn unary(&mut self) -> Res<Expr> {
+      self.traced("unary", |parser| {
+         let start = parser.advancer.next_pos();
 
-      let ast = if let Some((pos, ast)) = self.binary_type(
-         current, Syn::Not, Node::Not
-      )? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+         if parser.tok(Tok::Not) {
+            let expr = parser.required(|parser| parser.binary(NOT_PRECEDENCE + 1))?;
+            let span = Span::new(start, expr.span.end);
 
-      current = self.skip_space(current);
+            return Ok(Some(Expr {
+               kind: ExprKind::Not(Box::new(expr)),
+               span,
+            }));
+         }
 
-      if let Some((pos, _ast)) = self.expression(current)? {
-         current = pos;
-      } else {
-         println!("[{}] expected expression", current);
-         return Err(current);
-      }
+         if !parser.signed_literal() {
+            let negative = parser.advancer.peek_nth(0) == Some(&Tok::Minus);
 
-      ok(current, ast)
+            if parser.tok(Tok::Minus) || parser.tok(Tok::Plus) {
+               let expr = parser.required(|parser| parser.binary(SIGN_PRECEDENCE + 1))?;
+               let span = Span::new(start, expr.span.end);
+
+               let kind = if negative {
+                  ExprKind::Neg(Box::new(expr))
+               } else {
+                  ExprKind::Pos(Box::new(expr))
+               };
+
+               return Ok(Some(Expr { kind, span }));
+            }
+         }
+
+         parser.primary()
+      })
    }
 
-   fn binary_type(&self, pos: usize, syn: Syn, node: Node) -> Res {
-      let mut current = pos;
+   fn primary(&mut self) -> Res<Expr> {
+      self.traced("primary", |parser| {
+         if let Some(expr) = parser.call()? {
+            Ok(Some(expr))
+         } else if let Some(ident) = parser.ident() {
+            Ok(Some(Expr {
+               kind: ExprKind::Ident(ident.name),
+               span: ident.span,
+            }))
+         } else if let Some(expr) = parser.value()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.parens()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.list()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.map()? {
+            Ok(Some(expr))
+         } else if let Some(expr) = parser.lambda()? {
+            Ok(Some(expr))
+         } else {
+            Ok(None)
+         }
+      })
+   }
 
-      if let Some(pos) = self.token_type(current, syn) {
-         current = pos;
-      } else {
-         return no();
-      }
+   fn value(&mut self) -> Res<Expr> {
+      self.traced("value", |parser| {
+         let start = parser.advancer.next_pos();
+
+         let kind = if parser.number() {
+            ExprKind::Number(parser.span(start).text(parser.chars, parser.toks_meta))
+         } else if parser.symbol() {
+            ExprKind::Symbol(parser.text(start + 1))
+         } else if let Some(text) = parser.string()? {
+            ExprKind::String(text)
+         } else if parser.tok(Tok::True) {
+            ExprKind::Boolean(true)
+         } else if parser.tok(Tok::False) {
+            ExprKind::Boolean(false)
+         } else {
+            return Ok(None);
+         };
+
+         Ok(Some(Expr {
+            kind,
+            span: parser.span(start),
+         }))
+      })
+   }
+
+   fn call(&mut self) -> Res<Expr> {
+      self.traced("call", |parser| {
+         let pos = parser.advancer.pos();
+
+         let name = match parser.ident() {
+            Some(ref name) if parser.adjacent(Tok::ParenLeft) => name.clone(),
+            _ => {
+               parser.advancer.seek(pos);
+               return Ok(None);
+            }
+         };
+
+         let args = parser.items()?;
+         parser.expect(Tok::ParenRight)?;
 
-      ok(current, node)
+         Ok(Some(Expr {
+            span: parser.span(name.span.start),
+            kind: ExprKind::Call { name, args },
+         }))
+      })
    }
 
-   fn resulting(&self, pos: usize, indent: usize) -> Res {
-      if let Some((pos, ast)) = self.if_(pos, indent)? {
-         ok(pos, ast)
-      } else if let Some((pos, ast)) = self.match_(pos, indent)? {
-         ok(pos, ast)
-      } else if let Some((pos, ast)) = self.end_expression(pos)? {
-         ok(pos, ast)
-      } else {
-         no()
-      }
+   /// Expression in parentheses, or a list when there are none or several,
+   /// as in `()` or `(1 2)`.
+   fn parens(&mut self) -> Res<Expr> {
+      self.traced("parens", |parser| {
+         let start = parser.advancer.next_pos();
+
+         if !parser.tok(Tok::ParenLeft) {
+            return Ok(None);
+         }
+
+         let mut items = parser.items()?;
+         parser.expect(Tok::ParenRight)?;
+
+         let kind = if items.len() == 1 {
+            ExprKind::Parens(Box::new(items.remove(0)))
+         } else {
+            ExprKind::List(items)
+         };
+
+         Ok(Some(Expr {
+            kind,
+            span: parser.span(start),
+         }))
+      })
    }
 
-   fn end_expression(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// List in square brackets, a list even with a single item.
+   fn list(&mut self) -> Res<Expr> {
+      self.traced("list", |parser| {
+         let start = parser.advancer.next_pos();
 
-      let ast = if let Some((pos, ast)) = self.expression(current)? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+         if !parser.tok(Tok::SquareBracketLeft) {
+            return Ok(None);
+         }
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      } else {
-         return no();
-      }
+         let items = parser.items()?;
+         parser.expect(Tok::SquareBracketRight)?;
 
-      ok(current, ast)
-   }
-
-   fn expression(&self, pos: usize) -> Res {
-      let mut current = pos;
-
-      let ast = if let Some((pos, ast)) = self.fn_call(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.unary(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.ident(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.value(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.parens(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.list(current)? {
-         current = pos;
-         ast
-      } else if let Some((pos, ast)) = self.map(current)? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+         Ok(Some(Expr {
+            kind: ExprKind::List(items),
+            span: parser.span(start),
+         }))
+      })
+   }
 
-      current = self.skip_space(current);
+   /// Map in curly brackets. Line ends carry no meaning inside, so the
+   /// entries go on one line or on several, a value also on the line after
+   /// its key.
+   fn map(&mut self) -> Res<Expr> {
+      self.traced("map", |parser| {
+         let start = parser.advancer.next_pos();
 
-      if let Some((pos, ast)) = self.binary_right(current)? {
-         ok(pos, ast)
-      } else {
-         ok(current, ast)
-      }
+         if !parser.tok(Tok::CurlyBracketLeft) {
+            return Ok(None);
+         }
+
+         parser.line_ends();
+
+         let mut entries = Vec::new();
+
+         while let Some(entry) = parser.map_entry()? {
+            entries.push(entry);
+            parser.line_ends();
+         }
+
+         parser.expect(Tok::CurlyBracketRight)?;
+
+         Ok(Some(Expr {
+            kind: ExprKind::Map(entries),
+            span: parser.span(start),
+         }))
+      })
    }
 
-   fn value(&self, pos: usize) -> Res {
-      if let Some((pos, ast)) = self.number(pos)? {
-         ok(pos, ast)
-      } else if let Some((pos, ast)) = self.symbol(pos)? {
-         ok(pos, ast)
-      } else if let Some((pos, ast)) = self.accent(pos)? {
-         ok(pos, ast)
-      } else if let Some((pos, ast)) = self.string(pos)? {
-         ok(pos, ast)
-      } else {
-         no()
-      }
+   /// Lambda with an expression body, as in `|x y| x + y`.
+   fn lambda(&mut self) -> Res<Expr> {
+      self.traced("lambda", |parser| {
+         let start = parser.advancer.next_pos();
+
+         let params = match parser.lambda_params()? {
+            Some(params) => params,
+            None => return Ok(None),
+         };
+
+         let body = parser.required(Self::expression)?;
+         let span = Span::new(start, body.span.end);
+
+         Ok(Some(lambda(params, body, span)))
+      })
    }
 
-   fn fn_call(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// Lambda with its parameters ending the line and the body as the
+   /// indented block below.
+   fn lambda_block(&mut self) -> Res<Expr> {
+      self.traced("lambda block", |parser| {
+         let pos = parser.advancer.pos();
+         let start = parser.advancer.next_pos();
+
+         let params = match parser.lambda_params()? {
+            Some(params) => params,
+            None => return Ok(None),
+         };
+
+         if !parser.line_ends() {
+            parser.advancer.seek(pos);
+            return Ok(None);
+         }
 
-      if let Some((pos, _ast)) = self.ident(current)? {
-         current = pos;
-      } else {
-         return no();
-      };
+         let block = parser.indented_block()?;
+         let span = Span::new(start, block.span.end);
+         let body = Expr {
+            span: block.span,
+            kind: ExprKind::Block(block),
+         };
 
-      current = self.skip_space(current);
+         Ok(Some(lambda(params, body, span)))
+      })
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::ParenLeft) {
-         current = pos;
-      } else {
-         return no();
+   /// Parameters between vertical bars, `||` when there are none.
+   fn lambda_params(&mut self) -> Res<Vec<Ident>> {
+      if !self.tok(Tok::VerticalBar) {
+         return Ok(None);
       }
 
-      let _ast = if let Some((pos, ast)) = self.list_items(current)? {
-         current = pos;
-         ast
-      } else {
-         return Err(current);
-      };
+      let mut params = Vec::new();
 
-      if let Some(pos) = self.token_type(current, Syn::ParenRight) {
-         current = pos;
-      } else {
-         println!("[{}] expected )", current);
-         return Err(current);
+      while let Some(param) = self.ident() {
+         params.push(param);
       }
 
-      println!("[{}-{}] fn(...)", pos, current - 1);
+      self.expect(Tok::VerticalBar)?;
 
-      ok(current, Node::FnCall)
+      Ok(Some(params))
    }
 
-   fn break_(&self, pos: usize) -> Res {
-      let mut current = pos;
+   fn map_entry(&mut self) -> Res<MapEntry> {
+      self.traced("map entry", |parser| {
+         let key = match parser.expression()? {
+            Some(key) => key,
+            None => return Ok(None),
+         };
 
-      if let Some(pos) = self.token_type(current, Syn::Break) {
-         current = pos;
-      } else {
-         return no();
-      }
+         parser.expect(Tok::Colon)?;
+         parser.line_ends();
+         let value = parser.required(Self::expression)?;
+         let span = key.span.to(value.span);
 
-      if let Some(pos) = self.line_ends(current) {
-         current = pos;
-      } else {
-         println!("[{}] expected new line on break", current);
-         return Err(current);
+         Ok(Some(MapEntry { key, value, span }))
+      })
+   }
+
+   /// Expressions inside brackets, where line ends carry no meaning.
+   fn items(&mut self) -> Result<Vec<Expr>, usize> {
+      let mut items = Vec::new();
+
+      self.line_ends();
+
+      while let Some(expr) = self.expression()? {
+         items.push(expr);
+         self.line_ends();
       }
 
-      ok(current, Node::Break)
+      Ok(items)
    }
 
-   fn parens(&self, pos: usize) -> Res {
-      let mut current = pos;
+   fn ident(&mut self) -> Option<Ident> {
+      let start = self.advancer.next_pos();
 
-      if let Some(pos) = self.token_type(current, Syn::ParenLeft) {
-         current = pos;
+      if self.tok(Tok::Identifier) {
+         Some(Ident {
+            name: self.text(start),
+            span: self.span(start),
+         })
       } else {
-         return no();
+         None
       }
+   }
+
+   fn number(&mut self) -> bool {
+      let pos = self.advancer.pos();
 
-      let _ast = if let Some((pos, ast)) = self.expression(current)? {
-         current = pos;
-         ast
+      let signed = self.tok(Tok::Plus) || self.tok(Tok::Minus);
+
+      let integer = if signed {
+         self.adjacent(Tok::Digits)
       } else {
-         return Err(current);
+         self.tok(Tok::Digits)
       };
 
-      if let Some(pos) = self.token_type(current, Syn::ParenRight) {
-         current = pos;
+      let number = if integer {
+         if self.adjacent(Tok::FullStop) {
+            self.adjacent(Tok::Digits);
+         }
+         true
       } else {
-         println!("[{}] expected )", current);
-         return Err(current);
+         let point = if signed {
+            self.adjacent(Tok::FullStop)
+         } else {
+            self.tok(Tok::FullStop)
+         };
+         point && self.adjacent(Tok::Digits)
+      };
+
+      if !number {
+         self.advancer.seek(pos);
       }
 
-      println!("[{}-{}] (...)", pos, current - 1);
+      number
+   }
+
+   /// Whether a space separated sign directly followed by a number comes
+   /// next, as in `1 -2`, which is a list of two numbers rather than a
+   /// subtraction.
+   fn signed_number_ahead(&self) -> bool {
+      let pos = self.advancer.pos();
 
-      ok(current, Node::Parens)
+      let tok = |offset| self.toks.get(pos + offset);
+
+      tok(0) == Some(&Tok::Space)
+         && (tok(1) == Some(&Tok::Plus) || tok(1) == Some(&Tok::Minus))
+         && (tok(2) == Some(&Tok::Digits) || tok(2) == Some(&Tok::FullStop))
    }
 
-   fn list(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// Whether a number literal with its sign comes next. The sign goes to
+   /// the operand of `**` instead when one follows, as in `-2 ** 2`.
+   fn signed_literal(&mut self) -> bool {
+      let pos = self.advancer.pos();
+
+      let signed = match self.advancer.peek_nth(0) {
+         Some(&Tok::Minus) | Some(&Tok::Plus) => self.number(),
+         _ => false,
+      };
+
+      let literal = signed && {
+         self.continuation();
+         self.advancer.peek_nth(0) != Some(&Tok::DoubleAsterisk)
+      };
+
+      self.advancer.seek(pos);
 
-      if let Some(pos) = self.token_type(current, Syn::BracketLeft) {
-         current = pos;
+      literal
+   }
+
+   fn symbol(&mut self) -> bool {
+      let pos = self.advancer.pos();
+
+      if self.tok(Tok::Caret) && self.adjacent(Tok::Identifier) {
+         true
       } else {
-         return no();
+         self.advancer.seek(pos);
+         false
       }
+   }
 
-      let ast = if let Some((pos, ast)) = self.list_items(current)? {
-         current = pos;
-         ast
+   fn string(&mut self) -> Res<String> {
+      if !self.tok(Tok::Apostrophe) {
+         return Ok(None);
+      }
+
+      let text = if self.adjacent(Tok::Text) {
+         self.text(self.advancer.pos() - 1)
       } else {
-         return Err(current);
+         String::new()
       };
 
-      if let Some(pos) = self.token_type(current, Syn::BracketRight) {
-         current = pos;
+      if self.adjacent(Tok::Apostrophe) {
+         Ok(Some(text))
       } else {
-         println!("[{}] expected ]", current);
-         return Err(current);
+         Err(self.advancer.pos())
       }
+   }
 
-      println!("[{}-{}] [...]", pos, current - 1);
-
-      ok(current, ast)
+   /// Runs a rule that has to match at the current position.
+   fn required<T, F>(&mut self, rule: F) -> Result<T, usize>
+   where
+      F: FnOnce(&mut Self) -> Res<T>,
+   {
+      match rule(self)? {
+         Some(parsed) => Ok(parsed),
+         None => Err(self.advancer.next_pos()),
+      }
    }
 
-   fn list_items(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// Runs `rule`, reporting it to the trace hook when there is one.
+   fn traced<T, F>(&mut self, name: &str, rule: F) -> Res<T>
+   where
+      F: FnOnce(&mut Self) -> Res<T>,
+   {
+      let start = self.advancer.next_pos();
 
-      current = self.skip_white_space(current);
+      if let Some(ref mut trace) = self.trace {
+         trace.enter(name, start);
+      }
 
-      loop {
-         if let Some((pos, _ast)) = self.expression(current)? {
-            current = pos;
+      let result = rule(self);
 
-            current = self.skip_white_space(current)
-         } else {
-            return ok(current, Node::List);
+      if let Some(ref mut trace) = self.trace {
+         match result {
+            Ok(Some(_)) => trace.exit(name, start, self.advancer.pos()),
+            Ok(None) => trace.fail(name, start, start),
+            Err(pos) => trace.fail(name, start, pos),
          }
       }
-   }
-
-   fn single_line_list_items(&self, pos: usize) -> Res {
-      let mut current = pos;
 
-      current = self.skip_space(current);
+      result
+   }
 
-      loop {
-         if let Some((pos, _ast)) = self.expression(current)? {
-            current = pos;
+   /// Consumes the next token that is not trivia.
+   fn take(&mut self) {
+      let pos = self.advancer.next_pos();
+      self.advancer.seek(pos + 1);
 
-            current = self.skip_space(current)
-         } else {
-            return ok(current, Node::List);
-         }
+      if let Some(ref mut trace) = self.trace {
+         trace.matched(&self.toks[pos], pos);
       }
    }
 
-   fn map(&self, pos: usize) -> Res {
-      let mut current = pos;
+   fn operator<O: Copy>(&mut self, operators: &[(Tok, O)]) -> Option<O> {
+      let tok = self.advancer.peek_nth(0)?;
+      let &(_, op) = operators.iter().find(|op| &op.0 == tok)?;
+      self.take();
+      Some(op)
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::CurlyLeft) {
-         current = pos;
+   /// Matches `tok` after skipping trivia, leaving the position unchanged
+   /// when it does not match.
+   fn tok(&mut self, tok: Tok) -> bool {
+      if self.advancer.peek_nth(0) == Some(&tok) {
+         self.take();
+         true
       } else {
-         return no();
+         false
       }
+   }
 
-      let ast = if let Some((pos, ast)) = self.map_items(current)? {
-         current = pos;
-         ast
+   /// Matches `tok` directly at the current position, without trivia in
+   /// between.
+   fn adjacent(&mut self, tok: Tok) -> bool {
+      if self.toks.get(self.advancer.pos()) == Some(&tok) {
+         self.take();
+         true
       } else {
-         return Err(current);
-      };
+         false
+      }
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::CurlyRight) {
-         current = pos;
+   fn expect(&mut self, tok: Tok) -> Result<(), usize> {
+      if self.tok(tok) {
+         Ok(())
       } else {
-         println!("[{}] expected }}", current);
-         return Err(current);
+         Err(self.advancer.next_pos())
       }
+   }
 
-      println!("[{}-{}] {{...}}", pos, current - 1);
+   fn line_ends(&mut self) -> bool {
+      let mut consumed = false;
+
+      while self.tok(Tok::LineEnd) {
+         consumed = true;
+      }
 
-      ok(current, ast)
+      consumed
    }
 
-   fn map_items(&self, pos: usize) -> Res {
-      let mut current = pos;
+   fn expect_line_ends(&mut self) -> Result<(), usize> {
+      if self.line_ends() {
+         Ok(())
+      } else {
+         Err(self.advancer.next_pos())
+      }
+   }
 
-      current = self.skip_white_space(current);
+   /// Moves past a line end when the next line is a double indented
+   /// continuation of the current one.
+   fn continuation(&mut self) -> bool {
+      let pos = self.advancer.pos();
 
-      loop {
-         if let Some((pos, _ast)) = self.map_key_value(current)? {
-            current = pos;
-         } else {
-            return ok(current, Node::Map);
-         }
+      if self.tok(Tok::LineEnd)
+         && self.indented(self.indent + 2)
+         && self.advancer.peek_nth(0) != Some(&Tok::LineEnd)
+      {
+         true
+      } else {
+         self.advancer.seek(pos);
+         false
       }
    }
 
-   fn map_key_value(&self, pos: usize) -> Res {
-      let mut current = pos;
+   /// Whether the line starting at the current position is indented at
+   /// `level`.
+   fn indented(&self, level: usize) -> bool {
+      self.line_indentation() == level * self.indentation
+   }
 
-      let _key_ast = if let Some((pos, ast)) = self.expression(current)? {
-         current = pos;
-         ast
-      } else {
-         return no();
-      };
+   /// Whether the line starting at the current position is indented deeper
+   /// than the current level.
+   fn deeper(&self) -> bool {
+      !self.advancer.cannot_peek() && self.line_indentation() > self.indent * self.indentation
+   }
 
-      current = self.skip_space(current);
+   fn line_indentation(&self) -> usize {
+      let pos = self.advancer.pos();
 
-      if let Some(pos) = self.token_type(current, Syn::Colon) {
-         current = pos;
-      } else {
-         println!("[{}] expected :", current);
-         return Err(current);
+      match self.toks.get(pos) {
+         Some(&Tok::Space) => self.toks_meta[pos].span,
+         _ => 0,
       }
+   }
 
-      current = self.skip_space(current);
+   /// Span from `start` up to the current position.
+   fn span(&self, start: usize) -> Span {
+      Span::new(start, self.advancer.pos())
+   }
 
-      let _value_ast = if let Some((pos, ast)) = self.expression(current)? {
-         current = pos;
-         ast
-      } else {
-         println!("[{}] expected expression", current);
-         return Err(current);
-      };
+   /// Span of a simple statement, which ends before its line end.
+   fn statement_span(&self, start: usize) -> Span {
+      let mut end = self.advancer.pos();
 
-      current = self.skip_white_space(current);
+      while end > start && (self.toks[end - 1] == Tok::LineEnd || self.toks[end - 1].is_trivia()) {
+         end -= 1;
+      }
 
-      ok(current, Node::MapItem)
+      Span::new(start, end)
    }
 
-   fn pattern(&self, pos: usize) -> Res {
-      let mut current = pos;
-      let mut consumed = false;
+   fn text(&self, pos: usize) -> String {
+      Span::new(pos, pos + 1).text(self.chars, self.toks_meta)
+   }
+}
 
-      current = self.skip_white_space(current);
+fn lambda(params: Vec<Ident>, body: Expr, span: Span) -> Expr {
+   let captures = captures(&params, &body);
 
-      loop {
-         if let Some((pos, _ast)) = self.ident(current)? {
-            current = pos;
-            consumed = true;
-            current = self.skip_white_space(current)
-         } else if let Some((pos, _ast)) = self.value(current)? {
-            current = pos;
-            consumed = true;
-            current = self.skip_white_space(current)
-         } else {
-            if consumed {
-               return ok(current, Node::Pattern);
-            } else {
-               return no();
-            }
+   Expr {
+      kind: ExprKind::Lambda {
+         params,
+         body: Box::new(body),
+         captures,
+      },
+      span,
+   }
+}
+
+/// Tokenizes and parses a whole module.
+pub fn parse(chars: &[char]) -> Parsed {
+   let (toks, toks_meta, line_starts) = tokenize(chars);
+   Parser::new(chars, &toks, &toks_meta, &line_starts).parse()
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn parse_str(source: &str) -> Parsed {
+      let chars: Vec<_> = source.chars().collect();
+      parse(&chars)
+   }
+
+   /// Compact S-expression rendering of the statements, ignoring spans.
+   fn sexp(stmts: &[Stmt]) -> String {
+      stmts.iter().map(stmt).collect::<Vec<_>>().join(" ")
+   }
+
+   fn stmt(stmt: &Stmt) -> String {
+      match stmt.kind {
+         StmtKind::Fn(ref def) => {
+            let params: Vec<_> = def.params.iter().map(|p| p.name.clone()).collect();
+            format!(
+               "(fn {} ({}) {})",
+               def.name.name,
+               params.join(" "),
+               block(&def.body)
+            )
          }
+         StmtKind::For {
+            ref pattern,
+            ref iterable,
+            ref body,
+         } => format!("(for {} {} {})", pat(pattern), expr(iterable), block(body)),
+         StmtKind::Loop(ref body) => format!("(loop {})", block(body)),
+         StmtKind::Break => "break".to_string(),
+         StmtKind::Ret(None) => "(ret)".to_string(),
+         StmtKind::Ret(Some(ref value)) => format!("(ret {})", expr(value)),
+         StmtKind::Assign {
+            ref target,
+            op,
+            ref value,
+         } => format!("({:?} {} {})", op, pat(target), expr(value)),
+         StmtKind::Expr(ref value) => expr(value),
+         StmtKind::Error => "error".to_string(),
       }
    }
 
-   fn indent_space(&self, pos: usize, indent: usize) -> Option<usize> {
-      if indent == 0 {
-         return Some(pos);
-      }
+   fn block(block: &Block) -> String {
+      format!("{{{}}}", sexp(&block.stmts))
+   }
 
-      if let Some(token) = self.tokens.get(pos) {
-         if token.syn == Syn::Space {
-            if token.span == indent * INDENT {
-               println!("[{}] indent {}", pos, indent);
-               return Some(pos + 1);
-            } else {
-               println!("[{}] indent {} != {} * {}", pos, token.span, indent, INDENT);
-            }
+   fn exprs(exprs: &[Expr]) -> String {
+      exprs.iter().map(expr).collect::<Vec<_>>().join(" ")
+   }
+
+   fn expr(e: &Expr) -> String {
+      match e.kind {
+         ExprKind::Ident(ref name) | ExprKind::Number(ref name) => name.clone(),
+         ExprKind::Symbol(ref name) => format!("^{}", name),
+         ExprKind::String(ref text) => format!("'{}'", text),
+         ExprKind::Boolean(value) => value.to_string(),
+         ExprKind::Call { ref name, ref args } => format!("{}({})", name.name, exprs(args)),
+         ExprKind::Parens(ref inner) => expr(inner),
+         ExprKind::List(ref items) => format!("[{}]", exprs(items)),
+         ExprKind::Map(ref entries) => {
+            let entries: Vec<_> = entries
+               .iter()
+               .map(|entry| format!("{}: {}", expr(&entry.key), expr(&entry.value)))
+               .collect();
+            format!("{{{}}}", entries.join(" "))
          }
+         ExprKind::Not(ref inner) => format!("(not {})", expr(inner)),
+         ExprKind::Neg(ref inner) => format!("(- {})", expr(inner)),
+         ExprKind::Pos(ref inner) => format!("(+ {})", expr(inner)),
+         ExprKind::Binary {
+            op,
+            ref left,
+            ref right,
+         } => format!("({:?} {} {})", op, expr(left), expr(right)),
+         ExprKind::If {
+            ref condition,
+            ref then,
+            ref efs,
+            ref el,
+         } => {
+            let mut parts = vec![expr(condition), block(then)];
+            parts.extend(
+               efs.iter()
+                  .map(|ef| format!("(ef {} {})", expr(&ef.condition), block(&ef.then))),
+            );
+            parts.extend(el.iter().map(block));
+            format!("(if {})", parts.join(" "))
+         }
+         ExprKind::Match {
+            ref subject,
+            ref arms,
+         } => {
+            let arms: Vec<_> = arms
+               .iter()
+               .map(|arm| format!("{}: {}", pat(&arm.pattern), expr(&arm.body)))
+               .collect();
+            format!("(match {} {})", expr(subject), arms.join(" "))
+         }
+         ExprKind::Lambda {
+            ref params,
+            ref body,
+            ..
+         } => {
+            let params: Vec<_> = params.iter().map(|p| p.name.clone()).collect();
+            format!("(|{}| {})", params.join(" "), expr(body))
+         }
+         ExprKind::Block(ref inner) => block(inner),
       }
-
-      None
    }
 
-   fn ident(&self, pos: usize) -> Res {
-      if let Some(pos) = self.token_type(pos, Syn::Ident) {
-         ok(pos, Node::Ident)
-      } else {
-         no()
+   fn pat(pattern: &Pattern) -> String {
+      match pattern.kind {
+         PatternKind::Wildcard => "_".to_string(),
+         PatternKind::Ident(ref name) => name.clone(),
+         PatternKind::Value(ref value) => expr(value),
+         PatternKind::Range { ref start, ref end } => format!("{}..{}", expr(start), expr(end)),
+         PatternKind::List(ref patterns) => {
+            let patterns: Vec<_> = patterns.iter().map(pat).collect();
+            format!("[{}]", patterns.join(" "))
+         }
+         PatternKind::Map(ref entries) => {
+            let entries: Vec<_> = entries
+               .iter()
+               .map(|entry| format!("{}: {}", expr(&entry.key), pat(&entry.value)))
+               .collect();
+            format!("{{{}}}", entries.join(" "))
+         }
+         PatternKind::Multi(ref patterns) => {
+            let patterns: Vec<_> = patterns.iter().map(pat).collect();
+            format!("<{}>", patterns.join(" "))
+         }
       }
    }
 
-   fn number(&self, pos: usize) -> Res {
-      let mut current = pos;
-      let mut is_number = false;
+   macro_rules! assert_ast {
+      ($string:tt, $expected:expr) => {
+         let parsed = parse_str(indoc!($string));
+         assert_eq!(parsed.errors, vec![]);
+         assert_eq!(sexp(&parsed.module.body), $expected);
+      };
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::Add) {
-         current += pos;
-      } else if let Some(pos) = self.token_type(current, Syn::Subtract) {
-         current += pos;
-      }
+   macro_rules! assert_error {
+      ($string:tt, $line:expr, $col:expr) => {
+         let errors = parse_str(indoc!($string)).errors;
+         assert_eq!(errors.len(), 1);
+         assert_eq!((errors[0].line, errors[0].col), ($line, $col));
+      };
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::Digits) {
-         current += pos;
-         is_number = true;
-      }
+   #[test]
+   fn test_fn() {
+      assert_ast!(
+         "
+         fn add(x y)
+            x + y
+         ",
+         "(fn add (x y) {(Add x y)})"
+      );
+      assert_ast!(
+         "
+         fn space()
+            one_plus(' ')
+         ",
+         "(fn space () {one_plus(' ')})"
+      );
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::Dot) {
-         current += pos;
-      } else if !is_number {
-         return no();
-      }
+   #[test]
+   fn test_call() {
+      assert_ast!(
+         "
+         z = add(x y)
+         o = add(20 40)
+         xs = add(mul(10 30) mul(20 30))
+         ",
+         "(Assign z add(x y)) \
+          (Assign o add(20 40)) \
+          (Assign xs add(mul(10 30) mul(20 30)))"
+      );
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::Digits) {
-         current += pos;
-         is_number = true;
-      }
+   #[test]
+   fn test_lists() {
+      assert_ast!(
+         "
+         xs = 10 40 60
+         xs = dda(10) lum(5) vid(9)
+         xs = (
+            dda(10)
+            lum(5)
+            vid(9)
+         )
+         xs = (20 + 40) (30 - 15)
+         xs = [1 -2 .5 3.]
+         ",
+         "(Assign xs [10 40 60]) \
+          (Assign xs [dda(10) lum(5) vid(9)]) \
+          (Assign xs [dda(10) lum(5) vid(9)]) \
+          (Assign xs [(Add 20 40) (Subtract 30 15)]) \
+          (Assign xs [1 -2 .5 3.])"
+      );
+   }
 
-      if is_number {
-         ok(current, Node::Number)
-      } else {
-         no()
-      }
+   #[test]
+   fn test_maps() {
+      assert_ast!(
+         "
+         m = map
+            2: 100
+            6: 200
+            9: 250
+         m = map
+            ^first:
+               q = x * y
+               p = x + y
+               q + p
+            ^second: 200
+            ^third: 250
+         m = { 'a': 1 ^b: 2 }
+         ",
+         "(Assign m {2: 100 6: 200 9: 250}) \
+          (Assign m {^first: {(Assign q (Multiply x y)) (Assign p (Add x y)) (Add q p)} \
+          ^second: 200 ^third: 250}) \
+          (Assign m {'a': 1 ^b: 2})"
+      );
    }
 
-   fn line_ends(&self, pos: usize) -> Option<usize> {
-      let mut current = pos;
-      let mut consumed = false;
+   #[test]
+   fn test_map_name() {
+      assert_ast!(
+         "
+         x = map
+
+         y = 1
+         fn f(map)
+            map
+         ",
+         "(Assign x map) (Assign y 1) (fn f (map) {map})"
+      );
+   }
 
-      loop {
-         if let Some(pos) = self.line_end(current) {
-            current = pos;
-            consumed = true;
+   #[test]
+   fn test_if() {
+      assert_ast!(
+         "
+         if x == 10
+            10
+         el
+            40
+         if x == 10
+               and y == 20
+               and z == 50
+            10
+         el
+            40
+         ",
+         "(if (Equal x 10) {10} {40}) \
+          (if (And (And (Equal x 10) (Equal y 20)) (Equal z 50)) {10} {40})"
+      );
+   }
 
-            assert!(current < self.tokens.len() + 1);
+   #[test]
+   fn test_ef() {
+      assert_ast!(
+         "
+         y = if x == 10
+            10
+         ef x == 20
+            20
+         ef x == 30
+               or x == 40
+               and y
+            30
+         el
+            40
+         if a
+            1
+         ef b
+            2
+         z = 3
+         ",
+         "(Assign y (if (Equal x 10) {10} (ef (Equal x 20) {20}) \
+          (ef (Or (Equal x 30) (And (Equal x 40) y)) {30}) {40})) \
+          (if a {1} (ef b {2})) \
+          (Assign z 3)"
+      );
+      // The stray branch left by recovery is an error of its own.
+      let locations: Vec<_> = parse_str("if a\n   1\nef\n   2\n")
+         .errors
+         .iter()
+         .map(|error| (error.line, error.col))
+         .collect();
+      assert_eq!(locations, vec![(3, 3), (3, 1)]);
+      assert_error!("if a\n   1\nel\n   2\nef b\n   3\n", 5, 1);
+   }
 
-            if current == self.tokens.len() {
-               return Some(current)
-            }
+   #[test]
+   fn test_match() {
+      assert_ast!(
+         "
+         y = match x
+            10: 10
+            _: 40
+         match x
+            10:
+               y = 10
+               z = 50
+            _:
+               y = 40
+               z = 90
+         ",
+         "(Assign y (match x 10: 10 _: 40)) \
+          (match x 10: {(Assign y 10) (Assign z 50)} _: {(Assign y 40) (Assign z 90)})"
+      );
+   }
 
-            continue;
-         }
+   #[test]
+   fn test_loops() {
+      assert_ast!(
+         "
+         for key token in map
+            loop
+               if empty(key)
+                  break
+               ret ^err key token
+         ",
+         "(for <key token> map {(loop {(if empty(key) {break}) (ret [^err key token])})})"
+      );
+   }
 
-         if consumed {
-            return Some(current);
-         } else {
-            return None;
-         }
-      }
+   #[test]
+   fn test_patterns() {
+      assert_ast!(
+         "
+         match match_token(next)
+            token span back:
+               ret token
+            ^none: 0
+            [first _ 'x']: first
+            (key value): key
+            { ^ok: value ^at: [line col] }: value
+            0..9: true
+            -1: false
+         for [key value] in pairs
+            a b = f(key)
+         { ^x: x } = point
+         (n) += 1
+         ",
+         "(match match_token(next) <token span back>: {(ret token)} ^none: 0 \
+          [first _ 'x']: first [key value]: key {^ok: value ^at: [line col]}: value \
+          0..9: true -1: false) \
+          (for [key value] pairs {(Assign <a b> f(key))}) \
+          (Assign {^x: x} point) \
+          (Add n 1)"
+      );
+      assert_error!("a b += 1\n", 1, 5);
+      assert_error!("match x\n   [a b: 1\n", 2, 8);
+      assert_error!("for 0.. in xs\n   x\n", 1, 9);
    }
 
-   fn line_end(&self, pos: usize) -> Option<usize> {
-      let mut current = pos;
+   #[test]
+   fn test_lambdas() {
+      assert_ast!(
+         "
+         add = |x y| x + y
+         ws = filter(xs |c| c == ' ' or c == '\t')
+         f = ||
+            n += 1
+            n
+         m = map
+            ^first: |x|
+               x * 2
+         ",
+         "(Assign add (|x y| (Add x y))) \
+          (Assign ws filter(xs (|c| (Or (Equal c ' ') (Equal c '\t'))))) \
+          (Assign f (|| {(Add n 1) n})) \
+          (Assign m {^first: (|x| {(Multiply x 2)})})"
+      );
+      assert_ast!("|x| x |y| y\n", "[(|x| x) (|y| y)]");
+      assert_error!("f = |x y x + y\n", 1, 12);
+      assert_error!("f = |x|\ny\n", 2, 1);
+   }
 
-      if let Some(pos) = self.token_type(current, Syn::Space) {
-         current = pos;
-      }
+   macro_rules! assert_expr {
+      ($source:expr, $expected:expr) => {
+         let parsed = parse_str($source);
+         assert_eq!(parsed.errors, vec![]);
+         assert_eq!(sexp(&parsed.module.body), $expected);
+      };
+   }
 
-      if let Some(pos) = self.eol_eof(current) {
-         Some(pos)
-      } else {
-         None
-      }
+   #[test]
+   fn test_precedence() {
+      assert_expr!(
+         "1 + 2 * 3 == 7 and x",
+         "(And (Equal (Add 1 (Multiply 2 3)) 7) x)"
+      );
+      assert_expr!("a or b and c", "(Or a (And b c))");
+      assert_expr!("a and b or c and d", "(Or (And a b) (And c d))");
+      assert_expr!("not a == b and c", "(And (not (Equal a b)) c)");
+      assert_expr!("not a or b", "(Or (not a) b)");
+      assert_expr!("a == not b", "(Equal a (not b))");
+      assert_expr!("1 - 2 - 3", "(Subtract (Subtract 1 2) 3)");
+      assert_expr!("8 / 4 / 2", "(Divide (Divide 8 4) 2)");
+      assert_expr!("2 ** 3 ** 2", "(Power 2 (Power 3 2))");
+      assert_expr!("2 * 3 ** 2", "(Multiply 2 (Power 3 2))");
+      assert_expr!("-2 ** 2", "(- (Power 2 2))");
+      assert_expr!("-2 * 3", "(Multiply -2 3)");
+      assert_expr!("2 ** -2", "(Power 2 -2)");
+      assert_expr!("-x", "(- x)");
+      assert_expr!("+x", "(+ x)");
+      assert_expr!("- 2", "(- 2)");
+      assert_expr!("-x ** 2", "(- (Power x 2))");
+      assert_expr!("-x * y", "(Multiply (- x) y)");
+      assert_expr!("2 * -(a + b)", "(Multiply 2 (- (Add a b)))");
+      assert_expr!("a - -b", "(Subtract a (- b))");
+      assert_expr!("not -x < y", "(not (Less (- x) y))");
+      assert_expr!("(1 + 2) * 3", "(Multiply (Add 1 2) 3)");
+      assert_expr!("0..n + 1", "(Range 0 (Add n 1))");
+      assert_expr!("x < 0..10", "(Less x (Range 0 10))");
+      assert_expr!("a + b < c * d", "(Less (Add a b) (Multiply c d))");
+      assert_expr!("1 -2", "[1 -2]");
+      assert_expr!("1 - 2", "(Subtract 1 2)");
    }
 
-   fn eol_eof(&self, pos: usize) -> Option<usize> {
-      if let Some(token) = self.tokens.get(pos) {
-         if token.syn == Syn::NewLine {
-            println!("[{}] eol", pos);
-            Some(pos + 1)
-         } else {
-            None
-         }
-      } else {
-         println!("[{}] eof", pos);
-         Some(pos)
-      }
+   #[test]
+   fn test_precedence_continuation() {
+      assert_ast!(
+         "
+         fn f()
+            x = a
+                  + b * c
+                  or d
+         ",
+         "(fn f () {(Assign x (Or (Add a (Multiply b c)) d))})"
+      );
    }
 
-   fn accent(&self, pos: usize) -> Res {
-      if let Some(token) = self.tokens.get(pos) {
-         if token.syn == Syn::Accent {
-            let string = &self.input[token.pos + 1..token.pos + token.span];
-            println!("[{}] `{}", pos, string);
-            return ok(pos + 1, Node::String);
-         }
-      }
+   #[test]
+   fn test_chained_comparison() {
+      assert_error!("a < b < c\n", 1, 7);
+      assert_error!("a == b != c\n", 1, 8);
+      assert_error!("0..1..2\n", 1, 5);
+      assert_expr!("a < b and b < c", "(And (Less a b) (Less b c))");
+   }
+
+   #[test]
+   fn test_spans() {
+      let source = indoc!(
+         "
+         fn add(x y)
+            ret x + y
+         z = add(1 2)
+         "
+      );
+      let chars: Vec<_> = source.chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      let parsed = Parser::new(&chars, &toks, &toks_meta, &line_starts).parse();
+      assert_eq!(parsed.errors, vec![]);
+      let module = parsed.module;
+
+      let text = |span: Span| span.text(&chars, &toks_meta);
+
+      let def = match module.body[0].kind {
+         StmtKind::Fn(ref def) => def,
+         _ => unreachable!(),
+      };
+      assert_eq!(text(module.body[0].span), "fn add(x y)\n   ret x + y");
+      assert_eq!(text(def.params[1].span), "y");
+      assert_eq!(text(def.body.span), "ret x + y");
 
-      no()
+      let value = match module.body[1].kind {
+         StmtKind::Assign { ref value, .. } => value,
+         _ => unreachable!(),
+      };
+      assert_eq!(text(module.body[1].span), "z = add(1 2)");
+      assert_eq!(text(value.span), "add(1 2)");
+      assert_eq!(value.span.meta(&toks_meta).line, 3);
+      assert_eq!(value.span.meta(&toks_meta).col, 5);
    }
 
-   fn string(&self, pos: usize) -> Res {
-      if let Some(token) = self.tokens.get(pos) {
-         if token.syn == Syn::String {
-            let string = &self.input[token.pos + 1..token.pos + token.span - 1];
-            println!("[{}] '{}'", pos, string);
-            return ok(pos + 1, Node::String);
+   /// Expected parse of the example under each heading of `doc/syntax.md`.
+   const SYNTAX_DOC: &[(&str, &str)] = &[
+      ("Function", "(fn add (x y) {(Add x y)})"),
+      (
+         "Lambda",
+         "(Assign add (|x y| (Add x y))) \
+          (Assign spaces filter(xs (|c| (Equal c ' ')))) \
+          (Assign f (|x| {(Assign q (Multiply x y)) \
+          (Add q 1)}))",
+      ),
+      ("Call", "(Assign z add(x y)) (Assign o add(20 40))"),
+      ("Nesting", "(Assign xs add(mul(10 30) mul(20 30)))"),
+      ("List [1]", "(Assign xs [10 40 60])"),
+      ("List [2]", "(Assign xs [dda(10) lum(5) vid(9)])"),
+      ("List [3]", "(Assign xs [dda(10) lum(5) vid(9)])"),
+      ("List [4]", "(Assign xs [(Add 20 40) (Subtract 30 15)])"),
+      (
+         "List [5]",
+         "(Assign xs [10 [20 30] []]) (Assign ys [[dda(10) lum(5) vid(9)] 40])",
+      ),
+      ("Map [1]", "(Assign m {2: 100 6: 200 9: 250})"),
+      (
+         "Map [2]",
+         "(Assign m {^first: {(Assign q (Multiply x y)) \
+          (Assign p (Add x y)) \
+          (Add q p)} ^second: 200 ^third: 250})",
+      ),
+      ("Map [3]", "(Assign m {^first: 100 ^second: [200 250]})"),
+      (
+         "Map [4]",
+         "(Assign m {^first: {2: 100 6: 200} ^second: [10 20 30]})",
+      ),
+      (
+         "Map [5]",
+         "(Assign m {^first: {2: 100} ^second: [10 20 30] ^third: {^x: 1}})",
+      ),
+      ("If [1]", "(if (Equal x 10) {10} {40})"),
+      (
+         "If [2]",
+         "(if (And (And (Equal x 10) (Equal y 20)) (Equal z 50)) {10} {40})",
+      ),
+      (
+         "If [3]",
+         "(if (Equal x 10) {10} (ef (Or (Equal x 20) (Equal x 30)) {20}) {40})",
+      ),
+      ("Match [1]", "(Assign y (match x 10: 10 _: 40))"),
+      (
+         "Match [2]",
+         "(match x 10: {(Assign y 10) (Assign z 50)} _: {(Assign y 40) (Assign z 90)})",
+      ),
+      (
+         "Patterns",
+         "(for <key token> map {(Assign x 1)}) \
+          (match next <token span back>: token [first _]: first {^ok: value}: value \
+          0..9: ^digit _: ^none) \
+          (Assign <first rest> split(xs))",
+      ),
+      ("Operators", "(And (Equal (Add 1 (Multiply 2 3)) 7) x)"),
+      ("Indentation", ""),
+   ];
+
+   /// Headings and code of the examples in `doc/syntax.md`.
+   fn syntax_doc() -> Vec<(String, String)> {
+      let mut examples = Vec::new();
+      let mut heading = "";
+      let mut code: Option<String> = None;
+
+      for line in include_str!("../doc/syntax.md").lines() {
+         if let Some(title) = line.strip_prefix("### ") {
+            heading = title;
+         } else if line == "```" {
+            match code.take() {
+               Some(code) => examples.push((heading.to_string(), code)),
+               None => code = Some(String::new()),
+            }
+         } else if let Some(ref mut code) = code {
+            code.push_str(line);
+            code.push('\n');
          }
       }
 
-      no()
+      examples
    }
 
-   fn symbol(&self, pos: usize) -> Res {
-      if let Some(token) = self.tokens.get(pos) {
-         if token.syn == Syn::Symbol {
-            let ident = &self.input[token.pos + 1..token.pos + token.span];
-            println!("[{}] ^{}", pos, ident);
-            return ok(pos + 1, Node::Symbol);
-         }
+   #[test]
+   fn test_syntax_doc() {
+      let examples = syntax_doc();
+
+      assert_eq!(examples.len(), SYNTAX_DOC.len());
+
+      for ((heading, code), &(expected_heading, expected)) in examples.iter().zip(SYNTAX_DOC) {
+         let parsed = parse_str(code);
+         assert_eq!(heading, expected_heading);
+         assert_eq!(parsed.errors, vec![], "{}", heading);
+         assert_eq!(sexp(&parsed.module.body), expected, "{}", heading);
       }
+   }
 
-      no()
+   #[test]
+   fn test_lax_sources() {
+      assert_eq!(
+         parse_str(include_str!("../lax/tokenize.lax")).errors,
+         vec![]
+      );
+      assert_eq!(parse_str(include_str!("../lax/block.lax")).errors, vec![]);
    }
 
-   fn token_type(&self, pos: usize, syn: Syn) -> Option<usize> {
-      if let Some(token) = self.tokens.get(pos) {
-         if token.syn == syn {
-            println!("[{}] {:?}", pos, syn);
-            return Some(pos + 1);
-         }
-      }
+   #[test]
+   fn test_errors() {
+      assert_error!("fn f(x\n   x\n", 1, 7);
+      assert_error!("x = 1\n   y = 2\n", 2, 4);
+      assert_error!("if x\ny\n", 2, 1);
+      assert_error!("m = { 1 2 }\n", 1, 9);
+      assert_error!("y = match x\n", 2, 1);
+   }
+
+   #[test]
+   fn test_recovery() {
+      let parsed = parse_str(indoc!(
+         "
+         fn f(x
+            ret x
+         y = (1 +
+         z = 1
+         fn g()
+            a = )
+            b = 2
+               c = 3
+         d = 4
+         xs = (
+            1 2
+         ) +
+         e = 5
+         m = match x
+            1: a
+            2: )
+               b
+            3: c
+         n = match x
+            1: )
+         "
+      ));
+
+      let locations: Vec<_> = parsed
+         .errors
+         .iter()
+         .map(|error| (error.line, error.col))
+         .collect();
+
+      assert_eq!(
+         locations,
+         vec![(1, 7), (3, 9), (6, 8), (8, 7), (12, 4), (16, 7), (20, 7)]
+      );
+      assert_eq!(
+         sexp(&parsed.module.body),
+         "error error (Assign z 1) (fn g () {error (Assign b 2) error}) (Assign d 4) error (Assign e 5) \
+          (Assign m (match x 1: a 3: c)) error"
+      );
+   }
 
-      None
+   #[test]
+   fn test_recovery_spans() {
+      let source = "x = 1 +\ny = 2\n";
+      let chars: Vec<_> = source.chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      let parsed = Parser::new(&chars, &toks, &toks_meta, &line_starts).parse();
+
+      assert_eq!(parsed.module.body[0].kind, StmtKind::Error);
+      assert_eq!(
+         parsed.module.body[0].span.text(&chars, &toks_meta),
+         "x = 1 +"
+      );
+      assert_eq!(parsed.module.body[1].span.text(&chars, &toks_meta), "y = 2");
    }
 
-   fn skip_white_space(&self, pos: usize) -> usize {
-      let mut pos = pos;
+   #[derive(Default)]
+   struct Recorder {
+      events: Vec<String>,
+   }
 
-      loop {
-         let start = pos;
+   impl Trace for Recorder {
+      fn enter(&mut self, rule: &str, pos: usize) {
+         self.events.push(format!("{} {}", rule, pos));
+      }
 
-         pos = self.skip_space(pos);
+      fn exit(&mut self, _rule: &str, start: usize, end: usize) {
+         self.events.push(format!("ok {}-{}", start, end));
+      }
 
-         pos = self.skip_token_type(pos, Syn::NewLine);
+      fn matched(&mut self, tok: &Tok, pos: usize) {
+         self.events.push(format!("{:?} {}", tok, pos));
+      }
 
-         if pos == start {
-            return pos;
-         }
+      fn fail(&mut self, _rule: &str, _start: usize, pos: usize) {
+         self.events.push(format!("fail {}", pos));
       }
    }
 
-   fn skip_space(&self, pos: usize) -> usize {
-      self.skip_token_type(pos, Syn::Space)
+   #[test]
+   fn test_trace() {
+      let chars: Vec<_> = "break\n".chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      let mut recorder = Recorder::default();
+
+      let parsed = Parser::new(&chars, &toks, &toks_meta, &line_starts)
+         .with_trace(&mut recorder)
+         .parse();
+
+      assert_eq!(parsed.errors, vec![]);
+      assert_eq!(
+         recorder.events,
+         vec![
+            "statement 0",
+            "fn 0",
+            "fail 0",
+            "for 0",
+            "fail 0",
+            "loop 0",
+            "fail 0",
+            "break 0",
+            "Break 0",
+            "LineEnd 1",
+            "ok 0-2",
+            "ok 0-2",
+            "statement 2",
+            "fail 2",
+         ]
+      );
    }
 
-   fn skip_token_type(&self, pos: usize, syn: Syn) -> usize {
-      if let Some(token) = self.tokens.get(pos) {
-         if token.syn == syn {
-            println!("[{}] -> {:?}", pos, syn);
-            return pos + 1;
+   #[test]
+   fn test_trace_balanced() {
+      let chars: Vec<_> = include_str!("../lax/tokenize.lax").chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      let mut recorder = Recorder::default();
+
+      let parsed = Parser::new(&chars, &toks, &toks_meta, &line_starts)
+         .with_trace(&mut recorder)
+         .parse();
+
+      assert_eq!(parsed.errors, vec![]);
+
+      let mut depth = 0isize;
+      for event in &recorder.events {
+         if event.starts_with("ok ") || event.starts_with("fail ") {
+            depth -= 1;
+         } else if !event.starts_with(char::is_uppercase) {
+            depth += 1;
          }
+         assert!(depth >= 0);
       }
-
-      pos
+      assert_eq!(depth, 0);
    }
 }
@@ -0,0 +1,703 @@
+use std::collections::HashMap;
+
+use advancer::Lines;
+use grammar::{left_recursion, Builder, ElementId, Instruction};
+use tokenize::Tok;
+
+/// Problem in a text grammar, located at `line` and `col` of its source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarError {
+   pub issue: GrammarIssue,
+   pub line: usize,
+   pub col: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrammarIssue {
+   Syntax,
+   /// Rule name not recognized by the resolver.
+   UnknownElement(String),
+   /// `<Name>` not naming a `Tok` variant.
+   UnknownTok(String),
+   /// Reference to a rule that is never defined.
+   Undefined(String),
+   Redefined(String),
+   /// `(` without a matching `)`, or the other way around.
+   Unbalanced,
+   /// Rules that reach themselves without consuming a token, reported at
+   /// the first of them.
+   LeftRecursion(Vec<String>),
+}
+
+/// Instructions and element positions, as given by `Builder::destructure`.
+pub type Program<E> = (Vec<Instruction<E>>, Vec<usize>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Symbol {
+   Name(String),
+   Tok(String),
+   Indent(usize),
+   Skip,
+   Equals,
+   Slash,
+   Question,
+   Star,
+   Plus,
+   ParenLeft,
+   ParenRight,
+   /// Closes a rule right after its last symbol, when the next line starts
+   /// at the first column or the source ends.
+   End,
+   Invalid,
+}
+
+#[derive(Debug)]
+enum Term {
+   Choice(Vec<Term>),
+   Sequence(Vec<Term>),
+   Optional(Box<Term>),
+   Many(Box<Term>),
+   Some(Box<Term>),
+   Rule(String, usize),
+   Tok(Tok),
+   Indent(usize),
+   Skip,
+}
+
+#[derive(Debug)]
+struct Rule {
+   name: String,
+   offset: usize,
+   block: bool,
+   body: Term,
+}
+
+/// Compiles a PEG-like text grammar to the instructions `Builder` would
+/// assemble, `resolve` mapping every rule name to its element:
+///
+/// ```text
+/// # Comments run to the end of the line.
+/// Module = (Statement / <LineEnd>)*
+/// Statement = INDENT (If / Expression <LineEnd>)
+/// If = <If> _ Expression <LineEnd> Block
+/// block Block = Statement+
+/// ```
+///
+/// Rules start at the first column, indented lines continue the previous
+/// rule. `/` separates choices, `?`, `*` and `+` match zero or one, zero or
+/// more and one or more times, `<Name>` is a `Tok` variant, `_` skips
+/// optional space, and `INDENT` or `INDENT+n` matches the indentation of
+/// the current block, or `n` levels deeper. A `block` rule is matched one
+/// indentation level deeper.
+pub fn compile<E, F>(source: &str, mut resolve: F) -> Result<Program<E>, Vec<GrammarError>>
+where
+   E: ElementId,
+   F: FnMut(&str) -> Option<E>,
+{
+   let chars: Vec<char> = source.chars().collect();
+   let symbols = lex(&chars);
+
+   let mut parser = Parser {
+      symbols: &symbols,
+      pos: 0,
+      errors: Vec::new(),
+   };
+   let rules = parser.rules();
+   let mut errors = parser.errors;
+
+   let mut elements: HashMap<&str, E> = HashMap::new();
+   for rule in &rules {
+      match resolve(&rule.name) {
+         _ if elements.contains_key(rule.name.as_str()) => {
+            errors.push((GrammarIssue::Redefined(rule.name.clone()), rule.offset))
+         }
+         Some(element) if elements.values().any(|&defined| defined == element) => {
+            errors.push((GrammarIssue::Redefined(rule.name.clone()), rule.offset))
+         }
+         Some(element) => {
+            elements.insert(&rule.name, element);
+         }
+         None => errors.push((GrammarIssue::UnknownElement(rule.name.clone()), rule.offset)),
+      }
+   }
+
+   for rule in &rules {
+      rule.body.references(&mut |name, offset| {
+         if !rules.iter().any(|rule| rule.name == name) {
+            errors.push((GrammarIssue::Undefined(name.to_string()), offset));
+         }
+      });
+   }
+
+   if errors.is_empty() {
+      let mut b = Builder::new();
+      for rule in &rules {
+         let element = elements[rule.name.as_str()];
+         if rule.block {
+            b.block(element);
+         } else {
+            b.element(element);
+         }
+         rule.body.emit(&mut b, &elements);
+      }
+      let (instructions, positions) = b.destructure();
+
+      for cycle in left_recursion(&instructions, &positions) {
+         let rules: Vec<&Rule> = cycle
+            .iter()
+            .map(|&element| {
+               rules
+                  .iter()
+                  .find(|rule| elements[rule.name.as_str()] == element)
+                  .unwrap()
+            })
+            .collect();
+         let names = rules.iter().map(|rule| rule.name.clone()).collect();
+         errors.push((GrammarIssue::LeftRecursion(names), rules[0].offset));
+      }
+
+      if errors.is_empty() {
+         return Ok((instructions, positions));
+      }
+   }
+
+   errors.sort_by_key(|&(_, offset)| offset);
+
+   let lines = Lines::new(&chars);
+   Err(
+      errors
+         .into_iter()
+         .map(|(issue, offset)| {
+            let location = lines.location(offset);
+            GrammarError {
+               issue,
+               line: location.line,
+               col: location.col,
+            }
+         })
+         .collect(),
+   )
+}
+
+/// Symbols together with the offset of their first character.
+fn lex(chars: &[char]) -> Vec<(Symbol, usize)> {
+   let mut symbols = Vec::new();
+   let mut end = 0;
+
+   let mut i = 0;
+   while i < chars.len() {
+      let c = chars[i];
+
+      if c == '#' {
+         while i < chars.len() && chars[i] != '\n' && chars[i] != '\r' {
+            i += 1;
+         }
+         continue;
+      }
+
+      if c.is_whitespace() {
+         i += 1;
+         continue;
+      }
+
+      let start = i;
+      let line_start = i == 0 || chars[i - 1] == '\n' || chars[i - 1] == '\r';
+      if line_start && !symbols.is_empty() {
+         symbols.push((Symbol::End, end));
+      }
+
+      i += 1;
+      let symbol = match c {
+         '=' => Symbol::Equals,
+         '/' => Symbol::Slash,
+         '?' => Symbol::Question,
+         '*' => Symbol::Star,
+         '+' => Symbol::Plus,
+         '(' => Symbol::ParenLeft,
+         ')' => Symbol::ParenRight,
+         '<' => {
+            i = name_end(chars, i);
+            if i > start + 1 && chars.get(i) == Some(&'>') {
+               i += 1;
+               Symbol::Tok(chars[start + 1..i - 1].iter().collect())
+            } else {
+               i = start + 1;
+               Symbol::Invalid
+            }
+         }
+         _ if c == '_' || c.is_alphabetic() => {
+            i = name_end(chars, i);
+            let name: String = chars[start..i].iter().collect();
+            match name.as_str() {
+               "_" => Symbol::Skip,
+               "INDENT" => {
+                  let digits = digits_end(chars, i + 1);
+                  if chars.get(i) == Some(&'+') && digits > i + 1 {
+                     let levels: String = chars[i + 1..digits].iter().collect();
+                     i = digits;
+                     // Out of range level counts are a syntax error.
+                     levels.parse().map_or(Symbol::Invalid, Symbol::Indent)
+                  } else {
+                     Symbol::Indent(0)
+                  }
+               }
+               _ => Symbol::Name(name),
+            }
+         }
+         _ => Symbol::Invalid,
+      };
+
+      symbols.push((symbol, start));
+      end = i;
+   }
+
+   if !symbols.is_empty() {
+      symbols.push((Symbol::End, end));
+   }
+
+   symbols
+}
+
+fn name_end(chars: &[char], mut i: usize) -> usize {
+   while i < chars.len() && (chars[i] == '_' || chars[i].is_alphanumeric()) {
+      i += 1;
+   }
+   i
+}
+
+fn digits_end(chars: &[char], mut i: usize) -> usize {
+   while i < chars.len() && chars[i].is_ascii_digit() {
+      i += 1;
+   }
+   i
+}
+
+/// A rule either parses or fails with the issue and its offset, leaving the
+/// offending symbol unconsumed.
+type Res<T> = Result<T, (GrammarIssue, usize)>;
+
+struct Parser<'s> {
+   symbols: &'s [(Symbol, usize)],
+   pos: usize,
+   errors: Vec<(GrammarIssue, usize)>,
+}
+
+impl<'s> Parser<'s> {
+   /// Every rule that parses, recording an error and skipping to the next
+   /// rule for the others.
+   fn rules(&mut self) -> Vec<Rule> {
+      let mut rules = Vec::new();
+
+      while self.pos < self.symbols.len() {
+         match self.rule() {
+            Ok(rule) => rules.push(rule),
+            Err(error) => {
+               self.errors.push(error);
+               while self.peek() != &Symbol::End {
+                  self.pos += 1;
+               }
+               self.pos += 1;
+            }
+         }
+      }
+
+      rules
+   }
+
+   fn rule(&mut self) -> Res<Rule> {
+      let block = match (self.peek(), self.symbols.get(self.pos + 1)) {
+         (Symbol::Name(keyword), Some(&(Symbol::Name(_), _))) if keyword == "block" => {
+            self.pos += 1;
+            true
+         }
+         _ => false,
+      };
+
+      let (name, offset) = match self.symbols[self.pos] {
+         (Symbol::Name(ref name), offset) => (name.clone(), offset),
+         (_, offset) => return Err((GrammarIssue::Syntax, offset)),
+      };
+      self.pos += 1;
+
+      self.expect(&Symbol::Equals)?;
+
+      let body = self.choice()?;
+
+      match self.symbols[self.pos] {
+         (Symbol::End, _) => self.pos += 1,
+         (Symbol::ParenRight, offset) => return Err((GrammarIssue::Unbalanced, offset)),
+         (_, offset) => return Err((GrammarIssue::Syntax, offset)),
+      }
+
+      Ok(Rule {
+         name,
+         offset,
+         block,
+         body,
+      })
+   }
+
+   fn choice(&mut self) -> Res<Term> {
+      let mut alternatives = vec![self.sequence()?];
+
+      while self.peek() == &Symbol::Slash {
+         self.pos += 1;
+         alternatives.push(self.sequence()?);
+      }
+
+      Ok(if alternatives.len() == 1 {
+         alternatives.pop().unwrap()
+      } else {
+         Term::Choice(alternatives)
+      })
+   }
+
+   fn sequence(&mut self) -> Res<Term> {
+      let mut items = Vec::new();
+
+      while let Some(item) = self.postfix()? {
+         items.push(item);
+      }
+
+      match items.len() {
+         0 => Err((GrammarIssue::Syntax, self.offset())),
+         1 => Ok(items.pop().unwrap()),
+         _ => Ok(Term::Sequence(items)),
+      }
+   }
+
+   fn postfix(&mut self) -> Res<Option<Term>> {
+      let item = match self.primary()? {
+         Some(item) => Box::new(item),
+         None => return Ok(None),
+      };
+
+      let term = match *self.peek() {
+         Symbol::Question => Term::Optional(item),
+         Symbol::Star => Term::Many(item),
+         Symbol::Plus => Term::Some(item),
+         _ => return Ok(Some(*item)),
+      };
+      self.pos += 1;
+
+      Ok(Some(term))
+   }
+
+   fn primary(&mut self) -> Res<Option<Term>> {
+      let (ref symbol, offset) = self.symbols[self.pos];
+
+      let term = match *symbol {
+         Symbol::Name(ref name) => Term::Rule(name.clone(), offset),
+         Symbol::Tok(ref name) => match Tok::from_name(name) {
+            Some(tok) => Term::Tok(tok),
+            None => return Err((GrammarIssue::UnknownTok(name.clone()), offset)),
+         },
+         Symbol::Indent(levels) => Term::Indent(levels),
+         Symbol::Skip => Term::Skip,
+         Symbol::ParenLeft => {
+            self.pos += 1;
+            let group = self.choice()?;
+            match *self.peek() {
+               Symbol::ParenRight => {}
+               Symbol::End => return Err((GrammarIssue::Unbalanced, offset)),
+               _ => return Err((GrammarIssue::Syntax, self.offset())),
+            }
+            group
+         }
+         _ => return Ok(None),
+      };
+      self.pos += 1;
+
+      Ok(Some(term))
+   }
+
+   fn expect(&mut self, symbol: &Symbol) -> Res<()> {
+      if self.peek() == symbol {
+         self.pos += 1;
+         Ok(())
+      } else {
+         Err((GrammarIssue::Syntax, self.offset()))
+      }
+   }
+
+   fn peek(&self) -> &'s Symbol {
+      &self.symbols[self.pos].0
+   }
+
+   fn offset(&self) -> usize {
+      self.symbols[self.pos].1
+   }
+}
+
+impl Term {
+   fn emit<E: ElementId>(&self, b: &mut Builder<E>, elements: &HashMap<&str, E>) {
+      match *self {
+         Term::Choice(ref alternatives) => {
+            b.choice();
+            for alternative in alternatives {
+               alternative.emit(b, elements);
+            }
+            b.end();
+         }
+         Term::Sequence(ref items) => {
+            b.sequence();
+            for item in items {
+               item.emit(b, elements);
+            }
+            b.end();
+         }
+         Term::Optional(ref item) => {
+            b.zero_or_one();
+            item.emit(b, elements);
+            b.end();
+         }
+         Term::Many(ref item) => {
+            b.zero_or_more();
+            item.emit(b, elements);
+            b.end();
+         }
+         Term::Some(ref item) => {
+            b.sequence();
+            item.emit(b, elements);
+            b.zero_or_more();
+            item.emit(b, elements);
+            b.end();
+            b.end();
+         }
+         Term::Rule(ref name, _) => {
+            b.reference(elements[name.as_str()]);
+         }
+         Term::Tok(ref tok) => {
+            b.tok(tok.clone());
+         }
+         Term::Indent(levels) => {
+            b.indentation(levels);
+         }
+         Term::Skip => {
+            b.skip_space();
+         }
+      }
+   }
+
+   /// Calls `f` with the name and offset of every rule referenced.
+   fn references<F: FnMut(&str, usize)>(&self, f: &mut F) {
+      match *self {
+         Term::Choice(ref terms) | Term::Sequence(ref terms) => {
+            for term in terms {
+               term.references(f);
+            }
+         }
+         Term::Optional(ref term) | Term::Many(ref term) | Term::Some(ref term) => {
+            term.references(f)
+         }
+         Term::Rule(ref name, offset) => f(name, offset),
+         Term::Tok(_) | Term::Indent(_) | Term::Skip => {}
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[derive(Debug, Clone, Copy, PartialEq)]
+   enum Element {
+      Module,
+      Statement,
+      If,
+      Block,
+      Expression,
+      Operand,
+   }
+
+   impl ElementId for Element {
+      fn index(self) -> usize {
+         self as usize
+      }
+   }
+
+   fn resolve(name: &str) -> Option<Element> {
+      match name {
+         "Module" => Some(Element::Module),
+         "Statement" => Some(Element::Statement),
+         "If" => Some(Element::If),
+         "Block" => Some(Element::Block),
+         "Expression" => Some(Element::Expression),
+         "Operand" => Some(Element::Operand),
+         _ => None,
+      }
+   }
+
+   fn errors(source: &str) -> Vec<(GrammarIssue, usize, usize)> {
+      compile(source, resolve)
+         .unwrap_err()
+         .into_iter()
+         .map(|error| (error.issue, error.line, error.col))
+         .collect()
+   }
+
+   #[test]
+   #[rustfmt::skip]
+   fn test_compile() {
+      let mut b = Builder::new();
+
+      b.element(Element::Module)
+         .zero_or_more()
+            .choice()
+               .reference(Element::Statement)
+               .tok(Tok::LineEnd)
+            .end()
+         .end();
+
+      b.element(Element::Statement)
+         .sequence()
+            .indentation(0)
+            .choice()
+               .reference(Element::If)
+               .sequence()
+                  .reference(Element::Expression)
+                  .tok(Tok::LineEnd)
+               .end()
+            .end()
+         .end();
+
+      b.element(Element::If)
+         .sequence()
+            .tok(Tok::If)
+            .skip_space()
+            .reference(Element::Expression)
+            .zero_or_one()
+               .indentation(1)
+            .end()
+            .tok(Tok::LineEnd)
+            .reference(Element::Block)
+         .end();
+
+      b.block(Element::Block)
+         .sequence()
+            .reference(Element::Statement)
+            .zero_or_more()
+               .reference(Element::Statement)
+            .end()
+         .end();
+
+      b.element(Element::Expression)
+         .sequence()
+            .reference(Element::Operand)
+            .zero_or_more()
+               .sequence()
+                  .skip_space()
+                  .choice()
+                     .tok(Tok::Plus)
+                     .tok(Tok::And)
+                  .end()
+                  .skip_space()
+                  .reference(Element::Operand)
+               .end()
+            .end()
+         .end();
+
+      b.element(Element::Operand)
+         .choice()
+            .tok(Tok::Identifier)
+            .tok(Tok::Digits)
+         .end();
+
+      let compiled = compile(
+         indoc!(
+            "
+            # Statements and blank lines.
+            Module = (Statement / <LineEnd>)*
+
+            Statement = INDENT (If / Expression <LineEnd>)
+            If = <If> _ Expression INDENT+1?
+               <LineEnd> Block  # Continued.
+            block Block = Statement+
+
+            Expression =
+               Operand (_ (<Plus> / <And>) _ Operand)*
+            Operand = <Identifier> / <Digits>
+            "
+         ),
+         resolve,
+      );
+
+      assert_eq!(compiled, Ok(b.destructure()));
+   }
+
+   #[test]
+   fn test_compile_empty() {
+      let (instructions, elements) = compile::<Element, _>("# Nothing.\n", resolve).unwrap();
+      assert!(instructions.is_empty());
+      assert!(elements.is_empty());
+   }
+
+   #[test]
+   fn test_syntax() {
+      assert_eq!(
+         errors("Module = \nStatement <If>\nIf = <If> / \nOperand = <Digits> ? !\n"),
+         vec![
+            (GrammarIssue::Syntax, 1, 9),
+            (GrammarIssue::Syntax, 2, 11),
+            (GrammarIssue::Syntax, 3, 12),
+            (GrammarIssue::Syntax, 4, 22),
+         ]
+      );
+      assert_eq!(
+         errors("A = INDENT+99999999999999999999999 <Identifier>\n"),
+         vec![(GrammarIssue::Syntax, 1, 5)]
+      );
+   }
+
+   #[test]
+   fn test_unbalanced() {
+      assert_eq!(
+         errors("Module = (Statement / <LineEnd>*\nStatement = <If>) <LineEnd>\n"),
+         vec![
+            (GrammarIssue::Unbalanced, 1, 10),
+            (GrammarIssue::Unbalanced, 2, 17),
+         ]
+      );
+   }
+
+   #[test]
+   fn test_names() {
+      assert_eq!(
+         errors("Module = Statement*\nStatement = <Iff> / Other\nModule = <If>\nOther = <If>\n"),
+         vec![
+            (GrammarIssue::Undefined("Statement".to_string()), 1, 10),
+            (GrammarIssue::UnknownTok("Iff".to_string()), 2, 13),
+            (GrammarIssue::Redefined("Module".to_string()), 3, 1),
+            (GrammarIssue::UnknownElement("Other".to_string()), 4, 1),
+         ]
+      );
+   }
+
+   #[test]
+   fn test_left_recursion() {
+      assert_eq!(
+         errors(indoc!(
+            "
+            Module = Statement*
+            Statement = If / Expression
+            If = INDENT? Block <If>
+            block Block = If
+            Expression = Operand <Plus> Operand
+            Operand = <Digits> / Expression
+            "
+         )),
+         vec![
+            (
+               GrammarIssue::LeftRecursion(vec!["If".to_string(), "Block".to_string()]),
+               3,
+               1
+            ),
+            (
+               GrammarIssue::LeftRecursion(vec!["Expression".to_string(), "Operand".to_string()]),
+               5,
+               1
+            ),
+         ]
+      );
+   }
+}
@@ -0,0 +1,191 @@
+use indentation::{indent_setting, indentation_directive, Diagnostic, Issue};
+use tokenize::{Tok, TokMeta};
+
+/// Replacement of the chars in `start..end` with `text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+   pub start: usize,
+   pub end: usize,
+   pub text: String,
+}
+
+/// A unit of zero spaces, which cannot express any block structure.
+#[derive(Debug, PartialEq)]
+pub enum ReindentError {
+   ZeroFrom,
+   ZeroTo,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reindent {
+   pub edits: Vec<Edit>,
+   /// Lines left untouched because their indentation is not a multiple of
+   /// the source unit.
+   pub skipped: Vec<Diagnostic>,
+}
+
+/// Computes the edits rescaling the indentation of every line from unit
+/// `from` to unit `to`. Each line keeps its level, so double indented
+/// continuation lines stay double indented. Only leading spaces are
+/// replaced, along with the unit of an `indent=` directive if present.
+/// Either unit being zero is an error.
+#[allow(clippy::manual_is_multiple_of)] // Keeps the minimum Rust version.
+pub fn reindent(
+   chars: &[char],
+   toks: &[Tok],
+   toks_meta: &[TokMeta],
+   line_starts: &[usize],
+   from: usize,
+   to: usize,
+) -> Result<Reindent, ReindentError> {
+   if from == 0 {
+      return Err(ReindentError::ZeroFrom);
+   }
+
+   if to == 0 {
+      return Err(ReindentError::ZeroTo);
+   }
+
+   let mut edits = vec![];
+   let mut skipped = vec![];
+
+   if from == to {
+      return Ok(Reindent { edits, skipped });
+   }
+
+   if let Some((i, Some(_))) = indentation_directive(chars, toks, toks_meta, line_starts) {
+      edits.extend(indent_setting(chars, &toks_meta[i]).map(|value| Edit {
+         start: value.start,
+         end: value.end,
+         text: to.to_string(),
+      }));
+   }
+
+   for i in line_starts {
+      if toks[*i] != Tok::Space {
+         continue;
+      }
+
+      let meta = &toks_meta[*i];
+
+      if meta.span % from != 0 {
+         skipped.push(Diagnostic {
+            issue: Issue::NotMultiple,
+            tok: *i,
+            line: meta.line,
+            col: meta.span + 1,
+         });
+         continue;
+      }
+
+      edits.push(Edit {
+         start: meta.end - meta.span,
+         end: meta.end,
+         text: " ".repeat(meta.span / from * to),
+      });
+   }
+
+   edits.sort_by_key(|edit| edit.start);
+
+   Ok(Reindent { edits, skipped })
+}
+
+/// Applies non-overlapping edits sorted by position.
+pub fn apply(chars: &[char], edits: &[Edit]) -> String {
+   let mut result = String::with_capacity(chars.len());
+   let mut pos = 0;
+
+   for edit in edits {
+      debug_assert!(edit.start >= pos);
+      result.extend(&chars[pos..edit.start]);
+      result.push_str(&edit.text);
+      pos = edit.end;
+   }
+
+   result.extend(&chars[pos..]);
+
+   result
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   use tokenize::tokenize;
+
+   fn rewrite(source: &str, from: usize, to: usize) -> (String, Vec<usize>) {
+      let chars: Vec<_> = source.chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      let reindent = reindent(&chars, &toks, &toks_meta, &line_starts, from, to).unwrap();
+      let skipped = reindent.skipped.iter().map(|d| d.line).collect();
+      (apply(&chars, &reindent.edits), skipped)
+   }
+
+   #[test]
+   fn test_reindent() {
+      let (result, skipped) = rewrite(
+         indoc!(
+            "
+            fn f(x)
+              if x == 10
+                  and y == ' a  b  '
+                x
+              el
+                # comment
+                40
+            "
+         ),
+         2,
+         3,
+      );
+      assert_eq!(
+         result,
+         indoc!(
+            "
+            fn f(x)
+               if x == 10
+                     and y == ' a  b  '
+                  x
+               el
+                  # comment
+                  40
+            "
+         )
+      );
+      assert!(skipped.is_empty());
+   }
+
+   #[test]
+   fn test_reindent_skipped() {
+      let (result, skipped) = rewrite("x\n   x\n  x\n     x\r\n", 3, 2);
+      assert_eq!(result, "x\n  x\n  x\n     x\r\n");
+      assert_eq!(skipped, vec![3, 4]);
+   }
+
+   #[test]
+   fn test_reindent_directive() {
+      let (result, _) = rewrite("# lax: indent=4 \nx\n    x\n", 4, 2);
+      assert_eq!(result, "# lax: indent=2 \nx\n  x\n");
+
+      // Only the setting the directive is read from is rewritten.
+      let (result, _) = rewrite("# lax: noindent=4 indent=4\nx\n    x\n", 4, 2);
+      assert_eq!(result, "# lax: noindent=4 indent=2\nx\n  x\n");
+      let (result, _) = rewrite("# lax:indent=4\n# indent=4\nx\n    x\n", 4, 2);
+      assert_eq!(result, "# lax:indent=2\n# indent=4\nx\n  x\n");
+   }
+
+   #[test]
+   fn test_reindent_unchanged() {
+      assert_eq!(rewrite("x\n  x\n", 2, 2).0, "x\n  x\n");
+   }
+
+   #[test]
+   fn test_reindent_zero() {
+      let chars: Vec<_> = "x\n   y\n      z\n".chars().collect();
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      let reindent = |from, to| reindent(&chars, &toks, &toks_meta, &line_starts, from, to);
+      assert_eq!(reindent(3, 0), Err(ReindentError::ZeroTo));
+      assert_eq!(reindent(0, 2), Err(ReindentError::ZeroFrom));
+      assert_eq!(reindent(0, 0), Err(ReindentError::ZeroFrom));
+   }
+}
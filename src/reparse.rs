@@ -0,0 +1,748 @@
+use std::mem;
+use std::ops::Range;
+
+use advancer::Lines;
+use ast::{Block, Expr, ExprKind, Ident, Pattern, PatternKind, Span, Stmt, StmtKind};
+use blocks::BlockTree;
+use indentation::blank_line;
+use parse::{Parsed, Parser, SyntaxError};
+use scope;
+use tokenize::{try_tokenize, Tok, TokMeta, TokenizeError};
+
+/// Source kept together with its parse, so that an edit only re-parses the
+/// smallest indented block enclosing it.
+pub struct Document {
+   chars: Vec<char>,
+   toks: Vec<Tok>,
+   toks_meta: Vec<TokMeta>,
+   line_starts: Vec<usize>,
+   indentation: usize,
+   parsed: Parsed,
+}
+
+/// Outcome of `Document::edit`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reparse {
+   /// Block parsed again, the whole module when no indented block encloses
+   /// the edit or the edit changes the block structure around it.
+   pub span: Span,
+   /// Statements of that block that are new or differ from before the
+   /// edit. Everything else is reused, only moved.
+   pub changed: Vec<Span>,
+}
+
+/// Tokens of an edited source, before parsing.
+struct Source {
+   chars: Vec<char>,
+   toks: Vec<Tok>,
+   toks_meta: Vec<TokMeta>,
+   line_starts: Vec<usize>,
+}
+
+impl Document {
+   /// Fails when `chars` cannot be tokenized, as with an unterminated
+   /// string.
+   pub fn new(chars: Vec<char>) -> Result<Self, TokenizeError> {
+      let (toks, toks_meta, line_starts) = try_tokenize(&chars)?;
+      let mut parser = Parser::new(&chars, &toks, &toks_meta, &line_starts);
+      let indentation = parser.indentation();
+      let parsed = parser.parse();
+
+      Ok(Document {
+         chars,
+         toks,
+         toks_meta,
+         line_starts,
+         indentation,
+         parsed,
+      })
+   }
+
+   pub fn chars(&self) -> &[char] {
+      &self.chars
+   }
+
+   pub fn toks(&self) -> &[Tok] {
+      &self.toks
+   }
+
+   pub fn toks_meta(&self) -> &[TokMeta] {
+      &self.toks_meta
+   }
+
+   pub fn parsed(&self) -> &Parsed {
+      &self.parsed
+   }
+
+   /// Replaces the chars in `range` by `text`. The whole source is
+   /// tokenized again, but only the smallest indented block enclosing the
+   /// edited lines is parsed again, provided its lines still form the same
+   /// block afterwards. When the edited source cannot be tokenized, the
+   /// document is left as it was and the error returned.
+   pub fn edit(&mut self, range: Range<usize>, text: &str) -> Result<Reparse, TokenizeError> {
+      let lines = Lines::new(&self.chars);
+      let first = lines.location(range.start).line - 1;
+      // A line end removed along with the line it ends leaves the next line
+      // untouched.
+      let last = lines.location(range.end.max(range.start + 1) - 1).line - 1;
+
+      let mut chars = self.chars[..range.start].to_vec();
+      chars.extend(text.chars());
+      chars.extend_from_slice(&self.chars[range.end..]);
+
+      let (toks, toks_meta, line_starts) = try_tokenize(&chars)?;
+      let source = Source {
+         chars,
+         toks,
+         toks_meta,
+         line_starts,
+      };
+
+      let line_delta =
+         Lines::new(&source.chars).starts().len() as isize - lines.starts().len() as isize;
+
+      let (parsed, reparse) = match self.reparse_block(&source, first, last, line_delta) {
+         Some(reparsed) => reparsed,
+         None => self.reparse_module(&source),
+      };
+
+      let Source {
+         chars,
+         toks,
+         toks_meta,
+         line_starts,
+      } = source;
+
+      self.chars = chars;
+      self.toks = toks;
+      self.toks_meta = toks_meta;
+      self.line_starts = line_starts;
+      self.parsed = parsed;
+
+      Ok(reparse)
+   }
+
+   fn reparse_module(&mut self, source: &Source) -> (Parsed, Reparse) {
+      let mut parser = Parser::new(
+         &source.chars,
+         &source.toks,
+         &source.toks_meta,
+         &source.line_starts,
+      );
+      self.indentation = parser.indentation();
+      let parsed = parser.parse();
+
+      let reparse = Reparse {
+         span: parsed.module.span,
+         changed: changed(
+            &self.parsed.module.body,
+            &self.chars,
+            &self.toks_meta,
+            &parsed.module.body,
+            source,
+         ),
+      };
+
+      (parsed, reparse)
+   }
+
+   /// Re-parses the block headed by the innermost header line enclosing the
+   /// lines from `first` to `last` whose block structure is unchanged,
+   /// trying the headers further out in turn.
+   fn reparse_block(
+      &self,
+      source: &Source,
+      first: usize,
+      last: usize,
+      line_delta: isize,
+   ) -> Option<(Parsed, Reparse)> {
+      if last >= self.line_starts.len() {
+         return None;
+      }
+
+      // The block trees and the statements kept from the old parse assume
+      // the old indentation unit.
+      let parser = Parser::new(
+         &source.chars,
+         &source.toks,
+         &source.toks_meta,
+         &source.line_starts,
+      );
+      if parser.indentation() != self.indentation {
+         return None;
+      }
+
+      let old_tree = BlockTree::new(
+         &self.toks,
+         &self.toks_meta,
+         &self.line_starts,
+         self.indentation,
+      );
+      let new_tree = BlockTree::new(
+         &source.toks,
+         &source.toks_meta,
+         &source.line_starts,
+         self.indentation,
+      );
+
+      let mut header = old_tree.enclosing(first);
+      while let Some(line) = header {
+         let reparsed =
+            self.reparse_header(source, &old_tree, &new_tree, line, first, last, line_delta);
+         if reparsed.is_some() {
+            return reparsed;
+         }
+         header = old_tree.enclosing(line);
+      }
+
+      None
+   }
+
+   #[allow(clippy::too_many_arguments)]
+   fn reparse_header(
+      &self,
+      source: &Source,
+      old_tree: &BlockTree,
+      new_tree: &BlockTree,
+      header: usize,
+      first: usize,
+      last: usize,
+      line_delta: isize,
+   ) -> Option<(Parsed, Reparse)> {
+      let end = old_tree.end(header);
+      let body = (header + 1..end + 1).find(|&line| !old_tree.line(line).continuation)?;
+
+      let new_end = end as isize + line_delta;
+      if first < body || last > end || new_end < body as isize {
+         return None;
+      }
+      let new_end = new_end as usize;
+
+      let unchanged = new_tree.lines().len() > new_end
+         && new_tree.end(header) == new_end
+         && (header + 1..body + 1)
+            .all(|line| new_tree.line(line).continuation == old_tree.line(line).continuation);
+      if !unchanged {
+         return None;
+      }
+
+      let old_start = self.line_starts[body];
+      let target = (old_start..self.toks.len())
+         .find(|&pos| self.toks[pos] != Tok::LineEnd && !self.toks[pos].is_trivia())?;
+      let old_stop = next_line(&self.toks, &self.line_starts, end);
+      let new_stop = next_line(&source.toks, &source.line_starts, new_end);
+
+      let mut parser = Parser::new(
+         &source.chars,
+         &source.toks,
+         &source.toks_meta,
+         &source.line_starts,
+      );
+      let (block, stop, block_errors) =
+         parser.parse_block(source.line_starts[body], old_tree.depth(header) + 1)?;
+      if stop != new_stop {
+         return None;
+      }
+
+      let delta = source.toks.len() as isize - self.toks.len() as isize;
+      let span = block.span;
+      let new = block.stmts.clone();
+
+      let mut splice = Splice {
+         target,
+         stop: old_stop,
+         delta,
+         replacement: Some(block),
+         old_end: None,
+         new_start: span.start,
+         new_end: span.end,
+      };
+      let mut module = self.parsed.module.clone();
+      splice.stmts(&mut module.body);
+      splice.old_end?;
+      let old = splice.replacement.take()?;
+      module.span = Span::new(0, source.toks.len());
+
+      let mut errors: Vec<SyntaxError> = Vec::new();
+      errors.extend(
+         self
+            .parsed
+            .errors
+            .iter()
+            .filter(|error| error.tok < old_start)
+            .cloned(),
+      );
+      errors.extend(block_errors);
+      errors.extend(
+         self
+            .parsed
+            .errors
+            .iter()
+            .filter(|error| error.tok >= old_stop)
+            .map(|error| SyntaxError {
+               tok: (error.tok as isize + delta) as usize,
+               line: (error.line as isize + line_delta) as usize,
+               col: error.col,
+            }),
+      );
+
+      let reparse = Reparse {
+         span,
+         changed: changed(&old.stmts, &self.chars, &self.toks_meta, &new, source),
+      };
+
+      Some((Parsed { module, errors }, reparse))
+   }
+}
+
+/// Position of the first line after `line` that is not blank, where the
+/// parser stops after the block ending at `line`.
+fn next_line(toks: &[Tok], line_starts: &[usize], line: usize) -> usize {
+   line_starts[line + 1..]
+      .iter()
+      .find(|&&start| !blank_line(toks, start))
+      .map_or(toks.len(), |&start| start)
+}
+
+/// Spans of the statements of `new` between the statements both `old` and
+/// `new` start and end with unchanged.
+fn changed(
+   old: &[Stmt],
+   old_chars: &[char],
+   old_toks_meta: &[TokMeta],
+   new: &[Stmt],
+   source: &Source,
+) -> Vec<Span> {
+   let same = |old: &Stmt, new: &Stmt| {
+      old.kind == StmtKind::Error && new.kind == StmtKind::Error
+         || old.span.text(old_chars, old_toks_meta)
+            == new.span.text(&source.chars, &source.toks_meta)
+   };
+
+   let prefix = old
+      .iter()
+      .zip(new)
+      .take_while(|&(old, new)| same(old, new))
+      .count();
+   let suffix = old[prefix..]
+      .iter()
+      .rev()
+      .zip(new[prefix..].iter().rev())
+      .take_while(|&(old, new)| same(old, new))
+      .count();
+
+   new[prefix..new.len() - suffix]
+      .iter()
+      .map(|stmt| stmt.span)
+      .collect()
+}
+
+/// Swaps the block starting at token `target` for `replacement`, moving the
+/// spans after it by `delta` tokens. Nodes are moved after their children,
+/// so the old block is known when reaching the nodes enclosing it, which
+/// take on the start or end of the new block where they shared them with
+/// the old one.
+struct Splice {
+   target: usize,
+   /// Where the parser stopped after the old block.
+   stop: usize,
+   delta: isize,
+   /// The new block, then the old one once swapped.
+   replacement: Option<Block>,
+   old_end: Option<usize>,
+   new_start: usize,
+   new_end: usize,
+}
+
+impl Splice {
+   fn pos(&self, pos: usize) -> usize {
+      if pos >= self.stop {
+         (pos as isize + self.delta) as usize
+      } else if self.old_end.is_some_and(|end| pos >= end) {
+         self.new_end
+      } else if self.old_end.is_some() && pos == self.target {
+         self.new_start
+      } else {
+         pos
+      }
+   }
+
+   fn span(&self, span: &mut Span) {
+      *span = Span::new(self.pos(span.start), self.pos(span.end));
+   }
+
+   fn block(&mut self, block: &mut Block) {
+      if block.span.start == self.target && self.old_end.is_none() {
+         if let Some(replacement) = self.replacement.take() {
+            self.old_end = Some(block.span.end);
+            self.replacement = Some(mem::replace(block, replacement));
+            return;
+         }
+      }
+
+      self.stmts(&mut block.stmts);
+      self.span(&mut block.span);
+   }
+
+   fn stmts(&mut self, stmts: &mut [Stmt]) {
+      for stmt in stmts {
+         self.stmt(stmt);
+      }
+   }
+
+   fn stmt(&mut self, stmt: &mut Stmt) {
+      match stmt.kind {
+         StmtKind::Fn(ref mut def) => {
+            self.ident(&mut def.name);
+            for param in &mut def.params {
+               self.ident(param);
+            }
+            self.block(&mut def.body);
+            self.span(&mut def.span);
+         }
+         StmtKind::For {
+            ref mut pattern,
+            ref mut iterable,
+            ref mut body,
+         } => {
+            self.pattern(pattern);
+            self.expr(iterable);
+            self.block(body);
+         }
+         StmtKind::Loop(ref mut body) => self.block(body),
+         StmtKind::Ret(Some(ref mut value)) => self.expr(value),
+         StmtKind::Assign {
+            ref mut target,
+            ref mut value,
+            ..
+         } => {
+            self.pattern(target);
+            self.expr(value);
+         }
+         StmtKind::Expr(ref mut expr) => self.expr(expr),
+         StmtKind::Break | StmtKind::Ret(None) | StmtKind::Error => {}
+      }
+
+      self.span(&mut stmt.span);
+   }
+
+   fn ident(&mut self, ident: &mut Ident) {
+      self.span(&mut ident.span);
+   }
+
+   fn expr(&mut self, expr: &mut Expr) {
+      match expr.kind {
+         ExprKind::Call {
+            ref mut name,
+            ref mut args,
+         } => {
+            self.ident(name);
+            for arg in args {
+               self.expr(arg);
+            }
+         }
+         ExprKind::Parens(ref mut inner)
+         | ExprKind::Not(ref mut inner)
+         | ExprKind::Neg(ref mut inner)
+         | ExprKind::Pos(ref mut inner) => self.expr(inner),
+         ExprKind::List(ref mut items) => {
+            for item in items {
+               self.expr(item);
+            }
+         }
+         ExprKind::Map(ref mut entries) => {
+            for entry in entries {
+               self.expr(&mut entry.key);
+               self.expr(&mut entry.value);
+               self.span(&mut entry.span);
+            }
+         }
+         ExprKind::Binary {
+            ref mut left,
+            ref mut right,
+            ..
+         } => {
+            self.expr(left);
+            self.expr(right);
+         }
+         ExprKind::If {
+            ref mut condition,
+            ref mut then,
+            ref mut efs,
+            ref mut el,
+         } => {
+            self.expr(condition);
+            self.block(then);
+            for ef in efs {
+               self.expr(&mut ef.condition);
+               self.block(&mut ef.then);
+               self.span(&mut ef.span);
+            }
+            if let Some(ref mut el) = *el {
+               self.block(el);
+            }
+         }
+         ExprKind::Match {
+            ref mut subject,
+            ref mut arms,
+         } => {
+            self.expr(subject);
+            for arm in arms {
+               self.pattern(&mut arm.pattern);
+               self.expr(&mut arm.body);
+               self.span(&mut arm.span);
+            }
+         }
+         ExprKind::Lambda {
+            ref mut params,
+            ref mut body,
+            ref mut captures,
+         } => {
+            let spliced = self.old_end.is_some();
+            for param in params.iter_mut() {
+               self.ident(param);
+            }
+            self.expr(body);
+            // What the body captures changes along with the block within it.
+            if !spliced && self.old_end.is_some() {
+               *captures = scope::captures(params, body);
+            }
+         }
+         ExprKind::Block(ref mut block) => self.block(block),
+         ExprKind::Ident(_)
+         | ExprKind::Number(_)
+         | ExprKind::Symbol(_)
+         | ExprKind::String(_)
+         | ExprKind::Boolean(_) => {}
+      }
+
+      self.span(&mut expr.span);
+   }
+
+   fn pattern(&mut self, pattern: &mut Pattern) {
+      match pattern.kind {
+         PatternKind::Value(ref mut value) => self.expr(value),
+         PatternKind::Range {
+            ref mut start,
+            ref mut end,
+         } => {
+            self.expr(start);
+            self.expr(end);
+         }
+         PatternKind::Map(ref mut entries) => {
+            for entry in entries {
+               self.expr(&mut entry.key);
+               self.pattern(&mut entry.value);
+               self.span(&mut entry.span);
+            }
+         }
+         PatternKind::List(ref mut patterns) | PatternKind::Multi(ref mut patterns) => {
+            for pattern in patterns {
+               self.pattern(pattern);
+            }
+         }
+         PatternKind::Wildcard | PatternKind::Ident(_) => {}
+      }
+
+      self.span(&mut pattern.span);
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use parse::parse;
+   use tokenize::TokenizeIssue;
+
+   /// Edits `source`, checking the outcome against parsing the edited
+   /// source from scratch.
+   fn edit(source: &str, range: Range<usize>, text: &str) -> (Document, Reparse) {
+      let mut document = Document::new(source.chars().collect()).unwrap();
+      let reparse = document.edit(range.clone(), text).unwrap();
+
+      let mut edited: Vec<char> = source.chars().collect();
+      edited.splice(range.clone(), text.chars());
+      assert_eq!(document.chars(), &edited[..]);
+      assert_eq!(
+         document.parsed(),
+         &parse(&edited),
+         "{:?} {:?}",
+         range_text(source, &range),
+         text
+      );
+
+      (document, reparse)
+   }
+
+   fn range_text(source: &str, range: &Range<usize>) -> String {
+      source
+         .chars()
+         .skip(range.start)
+         .take(range.end - range.start)
+         .collect()
+   }
+
+   /// Text of each span.
+   fn texts(document: &Document, spans: &[Span]) -> Vec<String> {
+      spans
+         .iter()
+         .map(|span| span.text(document.chars(), document.toks_meta()))
+         .collect()
+   }
+
+   const SOURCE: &str = indoc!(
+      "
+      x = 1
+      fn f(a)
+         b = a + 1
+
+         loop
+            b += 1
+            break
+         ret b
+      y = f(x)
+      "
+   );
+
+   const EF_SOURCE: &str = "if a\n   1\nef b\n      or c\n   2\nel\n   3\n";
+
+   fn offset(needle: &str) -> usize {
+      SOURCE.find(needle).unwrap()
+   }
+
+   #[test]
+   fn test_reparse_block() {
+      let start = offset("b = a") + 4;
+      let (document, reparse) = edit(SOURCE, start..start + 1, "2 * a");
+      assert_eq!(
+         texts(&document, &[reparse.span]),
+         vec!["b = 2 * a + 1\n\n   loop\n      b += 1\n      break\n   ret b"]
+      );
+      assert_eq!(texts(&document, &reparse.changed), vec!["b = 2 * a + 1"]);
+   }
+
+   #[test]
+   fn test_reparse_nested() {
+      let start = offset("break");
+      let (document, reparse) = edit(SOURCE, start..start, "b -= 2\n      ");
+      assert_eq!(
+         texts(&document, &[reparse.span]),
+         vec!["b += 1\n      b -= 2\n      break"]
+      );
+      assert_eq!(texts(&document, &reparse.changed), vec!["b -= 2"]);
+
+      let start = offset("      break");
+      let (document, reparse) = edit(SOURCE, start..start + 12, "");
+      assert_eq!(texts(&document, &[reparse.span]), vec!["b += 1"]);
+      assert!(reparse.changed.is_empty());
+   }
+
+   #[test]
+   fn test_reparse_blank_lines() {
+      let start = offset("\n\n") + 1;
+      let (document, reparse) = edit(SOURCE, start..start, "   # Comment.\n");
+      match document.parsed().module.body[1].kind {
+         StmtKind::Fn(ref def) => assert_eq!(reparse.span, def.body.span),
+         _ => unreachable!(),
+      }
+      assert!(reparse.changed.is_empty());
+   }
+
+   #[test]
+   fn test_reparse_errors() {
+      let start = offset("b += 1") + 5;
+      let (document, reparse) = edit(SOURCE, start..start + 1, ")");
+      assert_eq!(document.parsed().errors.len(), 1);
+      assert_eq!(texts(&document, &reparse.changed), vec!["b += )"]);
+
+      let source = "fn f(\nx = 1\nfn g()\n   a = )\ny = 2\n";
+      let start = source.find(")\ny").unwrap();
+      let (document, _) = edit(source, start..start + 1, "1");
+      assert_eq!(document.parsed().errors.len(), 1);
+   }
+
+   #[test]
+   fn test_reparse_lambda() {
+      let source = "f = |x|\n   y = x\n   y + 1\n";
+      let start = source.find("= x").unwrap() + 2;
+      let (document, reparse) = edit(source, start..start + 1, "z");
+      assert_eq!(texts(&document, &[reparse.span]), vec!["y = z\n   y + 1"]);
+      match document.parsed().module.body[0].kind {
+         StmtKind::Assign {
+            value:
+               Expr {
+                  kind: ExprKind::Lambda { ref captures, .. },
+                  ..
+               },
+            ..
+         } => assert_eq!(captures, &vec!["z".to_string()]),
+         _ => unreachable!(),
+      }
+   }
+
+   #[test]
+   fn test_reparse_module() {
+      let start = offset("y = f");
+      let (document, reparse) = edit(SOURCE, start..start + 1, "z");
+      assert_eq!(reparse.span, document.parsed().module.span);
+      assert_eq!(texts(&document, &reparse.changed), vec!["z = f(x)"]);
+
+      // Moving a statement out of the block changes its structure.
+      let start = offset("   ret b");
+      let (document, reparse) = edit(SOURCE, start..start + 3, "");
+      assert_eq!(reparse.span, document.parsed().module.span);
+      assert_eq!(texts(&document, &reparse.changed).len(), 2);
+
+      // So does indenting the whole block by another level.
+      let start = offset("fn f(a)\n") + 8;
+      let (_, reparse) = edit(SOURCE, start..start, "      x\n");
+      assert_eq!(reparse.changed.len(), 1);
+   }
+
+   #[test]
+   fn test_reparse_sources() {
+      for source in &[
+         SOURCE,
+         EF_SOURCE,
+         include_str!("../lax/tokenize.lax"),
+         include_str!("../lax/block.lax"),
+      ] {
+         let line_ends: Vec<usize> = source.match_indices('\n').map(|(i, _)| i).collect();
+         for (i, &end) in line_ends.iter().enumerate() {
+            let start = if i == 0 { 0 } else { line_ends[i - 1] + 1 };
+            edit(source, end..end, " + 1");
+            edit(source, end..end, " (");
+            edit(source, start..end + 1, "");
+         }
+      }
+
+      // Edits changing the estimated indentation unit parse everything
+      // again.
+      let start = EF_SOURCE.find('1').unwrap();
+      let (document, reparse) = edit(EF_SOURCE, start..start, "  ");
+      assert_eq!(reparse.span, document.parsed().module.span);
+      let start = SOURCE.find("   b = a").unwrap();
+      edit(SOURCE, start..start + 2, "");
+   }
+
+   #[test]
+   fn test_reparse_tokenize_error() {
+      let error = |line, col| TokenizeError {
+         issue: TokenizeIssue::LineEndInString,
+         line,
+         col,
+      };
+
+      let chars: Vec<_> = "x = 'a\n".chars().collect();
+      assert_eq!(Document::new(chars).err(), Some(error(1, 7)));
+
+      // A failed edit leaves the document as it was.
+      let mut document = Document::new(SOURCE.chars().collect()).unwrap();
+      let parsed = document.parsed().clone();
+      let start = SOURCE.find("y =").unwrap();
+      assert_eq!(document.edit(start..start, "'"), Err(error(9, 10)));
+      assert_eq!(document.chars(), &SOURCE.chars().collect::<Vec<_>>()[..]);
+      assert_eq!(document.parsed(), &parsed);
+      assert!(document.edit(start..start, "'a' ").is_ok());
+   }
+}
@@ -0,0 +1,188 @@
+//! Word-at-a-time scanning over UTF-8 bytes.
+//!
+//! Each function looks at eight bytes per step and returns the length of the
+//! leading run it recognizes. Only ASCII bytes ever belong to a space,
+//! identifier or digit run, so for those the byte length equals the number
+//! of chars.
+
+const WORD: usize = 8;
+
+const ONES: u64 = 0x0101_0101_0101_0101;
+const HIGH: u64 = 0x8080_8080_8080_8080;
+const LOW: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+
+fn splat(byte: u8) -> u64 {
+   ONES * u64::from(byte)
+}
+
+fn load(bytes: &[u8]) -> u64 {
+   let mut word = [0; WORD];
+   word.copy_from_slice(&bytes[..WORD]);
+   u64::from_le_bytes(word)
+}
+
+/// High bit set in every byte of `ascii` which is in `lo..=hi`. Bytes of
+/// `ascii` must be below 0x80, which rules out carries between bytes.
+fn in_range(ascii: u64, lo: u8, hi: u8) -> u64 {
+   (ascii + splat(0x80 - lo)) & !(ascii + splat(0x7f - hi)) & HIGH
+}
+
+/// High bit set in every byte of `word` equal to `byte`.
+fn equal(word: u64, byte: u8) -> u64 {
+   let diff = word ^ splat(byte);
+   !(((diff & LOW) + LOW) | diff) & HIGH
+}
+
+/// Length of the run of bytes whose high bits are set in the mask returned
+/// by `accept`, falling back to `single` for the tail shorter than a word.
+fn run<A, S>(bytes: &[u8], accept: A, single: S) -> usize
+where
+   A: Fn(u64) -> u64,
+   S: Fn(u8) -> bool,
+{
+   let mut pos = 0;
+
+   while pos + WORD <= bytes.len() {
+      let rejected = !accept(load(&bytes[pos..])) & HIGH;
+      if rejected != 0 {
+         return pos + rejected.trailing_zeros() as usize / 8;
+      }
+      pos += WORD;
+   }
+
+   pos + bytes[pos..].iter().take_while(|b| single(**b)).count()
+}
+
+pub fn spaces(bytes: &[u8]) -> usize {
+   run(bytes, |word| equal(word, b' '), |b| b == b' ')
+}
+
+pub fn digits(bytes: &[u8]) -> usize {
+   run(
+      bytes,
+      |word| in_range(word & LOW, b'0', b'9') & !word,
+      |b| b.is_ascii_digit(),
+   )
+}
+
+pub fn identifier(bytes: &[u8]) -> usize {
+   run(
+      bytes,
+      |word| {
+         let ascii = word & LOW;
+         (in_range(ascii, b'a', b'z')
+            | in_range(ascii, b'A', b'Z')
+            | in_range(ascii, b'0', b'9')
+            | equal(word, b'_'))
+            & !word
+      },
+      |b| b.is_ascii_alphanumeric() || b == b'_',
+   )
+}
+
+/// Length of a string body up to the first quote, backslash or line break.
+pub fn string_body(bytes: &[u8]) -> usize {
+   run(
+      bytes,
+      |word| !(equal(word, b'\'') | equal(word, b'\\') | equal(word, b'\n') | equal(word, b'\r')),
+      |b| b != b'\'' && b != b'\\' && b != b'\n' && b != b'\r',
+   )
+}
+
+/// Number of chars in valid UTF-8 `bytes`.
+pub fn count_chars(bytes: &[u8]) -> usize {
+   let mut continuations = 0;
+
+   let mut pos = 0;
+   while pos + WORD <= bytes.len() {
+      let word = load(&bytes[pos..]);
+      continuations += (word & !(word << 1) & HIGH).count_ones() as usize;
+      pos += WORD;
+   }
+
+   continuations += bytes[pos..].iter().filter(|b| **b & 0xc0 == 0x80).count();
+
+   bytes.len() - continuations
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn naive(bytes: &[u8], f: fn(u8) -> bool) -> usize {
+      bytes.iter().take_while(|b| f(**b)).count()
+   }
+
+   fn samples() -> Vec<Vec<u8>> {
+      let mut samples = vec![];
+      for source in &[
+         "",
+         " ",
+         "        x",
+         "                  \n",
+         "abc_DEF_0123456789_xyz_ABC.",
+         "abcdefgh`",
+         "abcdefgh{",
+         "abcdefgh@",
+         "abcdefgh[",
+         "abcdefgh/",
+         "abcdefgh:",
+         "0123456789012345a",
+         "_0Zz9aA_@_0Zz9aA_",
+         "some text in a string' tail",
+         "escaped \\' quote",
+         "line\nbreak and more text",
+         "ЯaЯaЯ y̆ ЯaЯaЯ ЯaЯaЯ'",
+      ] {
+         let bytes = source.as_bytes();
+         for i in 0..bytes.len() + 1 {
+            samples.push(bytes[i..].to_vec());
+         }
+      }
+      samples.push((0..=255).collect());
+      samples
+   }
+
+   #[test]
+   fn test_runs() {
+      for bytes in samples() {
+         assert_eq!(spaces(&bytes), naive(&bytes, |b| b == b' '));
+         assert_eq!(digits(&bytes), naive(&bytes, |b| b.is_ascii_digit()));
+         assert_eq!(
+            identifier(&bytes),
+            naive(&bytes, |b| b.is_ascii_alphanumeric() || b == b'_')
+         );
+         assert_eq!(
+            string_body(&bytes),
+            naive(&bytes, |b| b != b'\''
+               && b != b'\\'
+               && b != b'\n'
+               && b != b'\r')
+         );
+      }
+   }
+
+   #[test]
+   fn test_all_bytes() {
+      for byte in 0..=255u8 {
+         let mut bytes = vec![b'a'; 7];
+         bytes.push(byte);
+         bytes.extend_from_slice(b"aaaaaaaa");
+         let expected = if byte.is_ascii_alphanumeric() || byte == b'_' {
+            16
+         } else {
+            7
+         };
+         assert_eq!(identifier(&bytes), expected);
+      }
+   }
+
+   #[test]
+   fn test_count_chars() {
+      for source in &["", "abc", "ЯaЯaЯ", "y̆y̆y̆y̆y̆", "aaaaaaaaaaЯЯЯЯЯЯЯЯЯ€€€€𝄞𝄞"]
+      {
+         let bytes = source.as_bytes();
+         assert_eq!(count_chars(bytes), source.chars().count());
+      }
+   }
+}
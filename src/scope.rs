@@ -0,0 +1,220 @@
+use ast::{AssignOp, Block, Expr, ExprKind, Ident, Pattern, PatternKind, Stmt, StmtKind};
+
+/// Names the body of a lambda reads without binding them itself, in the
+/// order of their first use. These are captured from the enclosing scopes
+/// when the lambda is created. Functions called by name are included, as a
+/// lambda assigned to a variable is called the same way.
+///
+/// A name counts as bound from the parameter, assignment, `for` or match
+/// arm pattern binding it up to the end of the lambda, so a body reading
+/// `x` before `x = 1` still captures it.
+pub fn captures(params: &[Ident], body: &Expr) -> Vec<String> {
+   let mut scope = Scope {
+      bound: params.iter().map(|param| param.name.clone()).collect(),
+      captures: Vec::new(),
+   };
+
+   scope.expr(body);
+
+   scope.captures
+}
+
+struct Scope {
+   bound: Vec<String>,
+   captures: Vec<String>,
+}
+
+impl Scope {
+   fn read(&mut self, name: &str) {
+      if !self.bound.iter().any(|bound| bound == name)
+         && !self.captures.iter().any(|captured| captured == name)
+      {
+         self.captures.push(name.to_string());
+      }
+   }
+
+   fn bind(&mut self, pattern: &Pattern) {
+      match pattern.kind {
+         PatternKind::Ident(ref name) => self.bound.push(name.clone()),
+         PatternKind::List(ref patterns) | PatternKind::Multi(ref patterns) => {
+            for pattern in patterns {
+               self.bind(pattern);
+            }
+         }
+         PatternKind::Map(ref entries) => {
+            for entry in entries {
+               self.bind(&entry.value);
+            }
+         }
+         PatternKind::Wildcard | PatternKind::Value(_) | PatternKind::Range { .. } => {}
+      }
+   }
+
+   fn block(&mut self, block: &Block) {
+      for stmt in &block.stmts {
+         self.stmt(stmt);
+      }
+   }
+
+   fn stmt(&mut self, stmt: &Stmt) {
+      match stmt.kind {
+         // Functions do not capture, only their name is bound.
+         StmtKind::Fn(ref def) => self.bound.push(def.name.name.clone()),
+         StmtKind::For {
+            ref pattern,
+            ref iterable,
+            ref body,
+         } => {
+            self.expr(iterable);
+            self.bind(pattern);
+            self.block(body);
+         }
+         StmtKind::Loop(ref body) => self.block(body),
+         StmtKind::Ret(Some(ref value)) | StmtKind::Expr(ref value) => self.expr(value),
+         StmtKind::Assign {
+            ref target,
+            op,
+            ref value,
+         } => {
+            // Compound operators read their target first.
+            if let PatternKind::Ident(ref name) = target.kind {
+               if op != AssignOp::Assign {
+                  self.read(name);
+               }
+            }
+            self.expr(value);
+            self.bind(target);
+         }
+         StmtKind::Break | StmtKind::Ret(None) | StmtKind::Error => {}
+      }
+   }
+
+   fn expr(&mut self, expr: &Expr) {
+      match expr.kind {
+         ExprKind::Ident(ref name) => self.read(name),
+         ExprKind::Call { ref name, ref args } => {
+            self.read(&name.name);
+            for arg in args {
+               self.expr(arg);
+            }
+         }
+         ExprKind::Parens(ref inner)
+         | ExprKind::Not(ref inner)
+         | ExprKind::Neg(ref inner)
+         | ExprKind::Pos(ref inner) => self.expr(inner),
+         ExprKind::List(ref items) => {
+            for item in items {
+               self.expr(item);
+            }
+         }
+         ExprKind::Map(ref entries) => {
+            for entry in entries {
+               self.expr(&entry.key);
+               self.expr(&entry.value);
+            }
+         }
+         ExprKind::Binary {
+            ref left,
+            ref right,
+            ..
+         } => {
+            self.expr(left);
+            self.expr(right);
+         }
+         ExprKind::If {
+            ref condition,
+            ref then,
+            ref efs,
+            ref el,
+         } => {
+            self.expr(condition);
+            self.block(then);
+            for ef in efs {
+               self.expr(&ef.condition);
+               self.block(&ef.then);
+            }
+            if let Some(ref el) = *el {
+               self.block(el);
+            }
+         }
+         ExprKind::Match {
+            ref subject,
+            ref arms,
+         } => {
+            self.expr(subject);
+            for arm in arms {
+               self.bind(&arm.pattern);
+               self.expr(&arm.body);
+            }
+         }
+         ExprKind::Lambda { ref captures, .. } => {
+            for name in captures {
+               self.read(name);
+            }
+         }
+         ExprKind::Block(ref block) => self.block(block),
+         ExprKind::Number(_) | ExprKind::Symbol(_) | ExprKind::String(_) | ExprKind::Boolean(_) => {
+         }
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use parse::parse;
+
+   /// Captures of the lambda assigned by the first statement of `source`.
+   fn captured(source: &str) -> Vec<String> {
+      let chars: Vec<_> = source.chars().collect();
+      let parsed = parse(&chars);
+      assert_eq!(parsed.errors, vec![]);
+
+      match parsed.module.body[0].kind {
+         StmtKind::Assign {
+            value:
+               Expr {
+                  kind: ExprKind::Lambda { ref captures, .. },
+                  ..
+               },
+            ..
+         } => captures.clone(),
+         _ => unreachable!(),
+      }
+   }
+
+   #[test]
+   fn test_captures() {
+      assert_eq!(captured("f = |x y| x + y\n"), Vec::<String>::new());
+      assert_eq!(captured("f = |x| x + y * y\n"), vec!["y"]);
+      assert_eq!(
+         captured("f = |c| empty(c) or c == stop\n"),
+         vec!["empty", "stop"]
+      );
+      assert_eq!(captured("f = || n\n"), vec!["n"]);
+      assert_eq!(
+         captured(indoc!(
+            "
+            f = |xs|
+               total += 1
+               for key value in xs
+                  seen = key value limit
+               ret seen |y| y + offset
+            "
+         )),
+         vec!["total", "limit", "offset"]
+      );
+      assert_eq!(
+         captured(indoc!(
+            "
+            f = |x|
+               y = match x
+                  ^ok value: value
+                  _: fallback
+               y
+            "
+         )),
+         vec!["fallback"]
+      );
+   }
+}
@@ -1,37 +1,46 @@
-use advancer::Advancer;
+use std::fmt;
+use std::sync::OnceLock;
+
+use advancer::{Advancer, Location};
+use dispatch::{Dispatch, MAX_PATTERN};
+use scan;
+
+/// Declares `Tok` together with the spelling of every fixed-spelling token.
+/// The resulting `PUNCTUATION` spec can be listed in any order, `Dispatch`
+/// works out which pattern to try first.
+macro_rules! toks {
+   (
+      $($tok:ident,)*
+      ;
+      $($punctuation:ident = $pattern:expr,)*
+   ) => {
+      #[derive(Debug, Clone, PartialEq)]
+      pub enum Tok {
+         $($tok,)*
+         $($punctuation,)*
+      }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Tok {
+      pub const PUNCTUATION: &[(&str, Tok)] = &[
+         $(($pattern, Tok::$punctuation),)*
+      ];
+
+      impl Tok {
+         /// Token with the variant name `name`, as in `"DoubleEquals"`.
+         pub fn from_name(name: &str) -> Option<Tok> {
+            match name {
+               $(stringify!($tok) => Some(Tok::$tok),)*
+               $(stringify!($punctuation) => Some(Tok::$punctuation),)*
+               _ => None,
+            }
+         }
+      }
+   };
+}
+
+toks! {
    Indent,
    Space,
    LineEnd,
-   DoubleAsterisk,
-   DoubleEquals,
-   ExclamationEquals,
-   LessThanEquals,
-   GreaterThanEquals,
-   PlusEquals,
-   MinusEquals,
-   AsteriskEquals,
-   SlashEquals,
-   DoubleFullStop,
-   FullStop,
-   Equals,
-   Plus,
-   Minus,
-   Asterisk,
-   Slash,
-   VerticalBar,
-   Colon,
-   Caret,
-   ParenLeft,
-   ParenRight,
-   SquareBracketLeft,
-   SquareBracketRight,
-   LessThan,
-   GreaterThan,
-   CurlyBracketLeft,
-   CurlyBracketRight,
    Comment,
    Apostrophe,
    Text,
@@ -52,6 +61,40 @@ pub enum Tok {
    Break,
    False,
    Match,
+   ;
+   DoubleAsterisk = "**",
+   DoubleEquals = "==",
+   ExclamationEquals = "!=",
+   LessThanEquals = "<=",
+   GreaterThanEquals = ">=",
+   PlusEquals = "+=",
+   MinusEquals = "-=",
+   AsteriskEquals = "*=",
+   SlashEquals = "/=",
+   DoubleFullStop = "..",
+   FullStop = ".",
+   Equals = "=",
+   Plus = "+",
+   Minus = "-",
+   Asterisk = "*",
+   Slash = "/",
+   VerticalBar = "|",
+   Colon = ":",
+   Caret = "^",
+   ParenLeft = "(",
+   ParenRight = ")",
+   SquareBracketLeft = "[",
+   SquareBracketRight = "]",
+   LessThan = "<",
+   GreaterThan = ">",
+   CurlyBracketLeft = "{",
+   CurlyBracketRight = "}",
+}
+
+impl Tok {
+   pub fn is_trivia(&self) -> bool {
+      matches!(*self, Tok::Space | Tok::Comment)
+   }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -62,6 +105,41 @@ pub struct TokMeta {
    pub col: usize,
 }
 
+/// Source that cannot be tokenized, located at `line` and `col`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenizeError {
+   pub issue: TokenizeIssue,
+   pub line: usize,
+   pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenizeIssue {
+   Unrecognized,
+   LineEndInString,
+}
+
+impl fmt::Display for TokenizeError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      let issue = match self.issue {
+         TokenizeIssue::Unrecognized => "Unrecognized token",
+         TokenizeIssue::LineEndInString => "New line in string",
+      };
+
+      write!(f, "{} at line: {}, col: {}", issue, self.line, self.col)
+   }
+}
+
+/// Tokens, their metadata and the index of the first token of every line.
+pub type Tokens = (Vec<Tok>, Vec<TokMeta>, Vec<usize>);
+
+/// Advancer over the token stream which skips `Space` and `Comment` tokens.
+pub type TokAdvancer<'a> = Advancer<'a, Tok>;
+
+pub fn tok_advancer(toks: &[Tok]) -> TokAdvancer<'_> {
+   TokAdvancer::with_trivia(toks, Tok::is_trivia)
+}
+
 type TokMatch = Option<(Tok, usize)>;
 
 type CharAdvancer<'a> = Advancer<'a, char>;
@@ -111,100 +189,60 @@ fn identifier(advancer: &mut CharAdvancer) -> TokMatch {
       }) as FnMatcher,
    );
 
-   let tok = try_keyword(advancer);
+   let tok = try_keyword(advancer.current());
 
    Some((tok, advancer.consume()))
 }
 
-fn try_keyword(advancer: &CharAdvancer) -> Tok {
-   let w = advancer.current();
+fn try_keyword<T: Copy + Into<char>>(w: &[T]) -> Tok {
+   let c = |i: usize| -> char { unsafe { (*w.get_unchecked(i)).into() } };
    let len = w.len();
    match len {
-      2 => match unsafe { *w.get_unchecked(0) } {
-         'e' => match unsafe { *w.get_unchecked(1) } {
+      2 => match c(0) {
+         'e' => match c(1) {
             'f' => return Tok::Ef,
             'l' => return Tok::El,
             _ => {}
          },
-         'i' => match unsafe { *w.get_unchecked(1) } {
+         'i' => match c(1) {
             'f' => return Tok::If,
             'n' => return Tok::In,
             _ => {}
          },
-         'f' => if unsafe { *w.get_unchecked(1) == 'n' } {
-            return Tok::Fn;
-         },
-         'o' => if unsafe { *w.get_unchecked(1) == 'r' } {
-            return Tok::Or;
-         },
+         'f' if c(1) == 'n' => return Tok::Fn,
+         'o' if c(1) == 'r' => return Tok::Or,
          _ => {}
       },
       3 => {
-         if unsafe {
-            *w.get_unchecked(0) == 'a' && *w.get_unchecked(1) == 'n' && *w.get_unchecked(2) == 'd'
-         } {
+         if c(0) == 'a' && c(1) == 'n' && c(2) == 'd' {
             return Tok::And;
          }
-         if unsafe {
-            *w.get_unchecked(0) == 'f' && *w.get_unchecked(1) == 'o' && *w.get_unchecked(2) == 'r'
-         } {
+         if c(0) == 'f' && c(1) == 'o' && c(2) == 'r' {
             return Tok::For;
          }
-         if unsafe {
-            *w.get_unchecked(0) == 'n' && *w.get_unchecked(1) == 'o' && *w.get_unchecked(2) == 't'
-         } {
+         if c(0) == 'n' && c(1) == 'o' && c(2) == 't' {
             return Tok::Not;
          }
-         if unsafe {
-            *w.get_unchecked(0) == 'r' && *w.get_unchecked(1) == 'e' && *w.get_unchecked(2) == 't'
-         } {
+         if c(0) == 'r' && c(1) == 'e' && c(2) == 't' {
             return Tok::Ret;
          }
       }
       4 => {
-         if unsafe {
-            *w.get_unchecked(0) == 'l'
-               && *w.get_unchecked(1) == 'o'
-               && *w.get_unchecked(2) == 'o'
-               && *w.get_unchecked(3) == 'p'
-         } {
+         if c(0) == 'l' && c(1) == 'o' && c(2) == 'o' && c(3) == 'p' {
             return Tok::Loop;
          }
-         if unsafe {
-            *w.get_unchecked(0) == 't'
-               && *w.get_unchecked(1) == 'r'
-               && *w.get_unchecked(2) == 'u'
-               && *w.get_unchecked(3) == 'e'
-         } {
+         if c(0) == 't' && c(1) == 'r' && c(2) == 'u' && c(3) == 'e' {
             return Tok::True;
          }
       }
       5 => {
-         if unsafe {
-            *w.get_unchecked(0) == 'b'
-               && *w.get_unchecked(1) == 'r'
-               && *w.get_unchecked(2) == 'e'
-               && *w.get_unchecked(3) == 'a'
-               && *w.get_unchecked(4) == 'k'
-         } {
+         if c(0) == 'b' && c(1) == 'r' && c(2) == 'e' && c(3) == 'a' && c(4) == 'k' {
             return Tok::Break;
          }
-         if unsafe {
-            *w.get_unchecked(0) == 'f'
-               && *w.get_unchecked(1) == 'a'
-               && *w.get_unchecked(2) == 'l'
-               && *w.get_unchecked(3) == 's'
-               && *w.get_unchecked(4) == 'e'
-         } {
+         if c(0) == 'f' && c(1) == 'a' && c(2) == 'l' && c(3) == 's' && c(4) == 'e' {
             return Tok::False;
          }
-         if unsafe {
-            *w.get_unchecked(0) == 'm'
-               && *w.get_unchecked(1) == 'a'
-               && *w.get_unchecked(2) == 't'
-               && *w.get_unchecked(3) == 'c'
-               && *w.get_unchecked(4) == 'h'
-         } {
+         if c(0) == 'm' && c(1) == 'a' && c(2) == 't' && c(3) == 'c' && c(4) == 'h' {
             return Tok::Match;
          }
       }
@@ -220,85 +258,35 @@ fn digits(advancer: &mut CharAdvancer) -> TokMatch {
    Some((Tok::Digits, advancer.consume()))
 }
 
-macro_rules! exact {
-   ($c1:expr, $func:ident, $token_type:expr) => {
-      fn $func(advancer: &mut CharAdvancer) -> TokMatch {
-         debug_assert!(!advancer.completed());
-         advancer.one($c1)?;
-         Some(($token_type, advancer.consume()))
-      }
-   };
+fn comment(advancer: &mut CharAdvancer) -> TokMatch {
+   advancer.one('#')?;
+   advancer.zero_or_more((|c| *c != '\n' && *c != '\r') as FnMatcher);
 
-   ($c1:expr, $c2:expr, $func:ident, $token_type:expr) => {
-      fn $func(advancer: &mut CharAdvancer) -> TokMatch {
-         debug_assert!(!advancer.completed());
-         advancer.one($c1)?;
-         advancer.one($c2)?;
-         Some(($token_type, advancer.consume()))
-      }
-   };
+   Some((Tok::Comment, advancer.consume()))
+}
+
+/// Chars that start a token recognized by a matcher other than `Dispatch`.
+fn claimed(c: char) -> bool {
+   c == ' '
+      || c == '\r'
+      || c == '\n'
+      || c == '\''
+      || c == '#'
+      || c == '_'
+      || c.is_ascii_alphanumeric()
+}
+
+fn dispatch() -> &'static Dispatch<Tok> {
+   static DISPATCH: OnceLock<Dispatch<Tok>> = OnceLock::new();
+   DISPATCH.get_or_init(|| Dispatch::new(PUNCTUATION, claimed).unwrap())
+}
+
+fn punctuation(advancer: &mut CharAdvancer) -> TokMatch {
+   dispatch().run(advancer)
 }
 
-exact!('*', '*', double_asterisk, Tok::DoubleAsterisk);
-exact!('=', '=', double_equals, Tok::DoubleEquals);
-exact!('!', '=', exclamation_equals, Tok::ExclamationEquals);
-exact!('<', '=', less_than_equals, Tok::LessThanEquals);
-exact!('>', '=', greater_than_equals, Tok::GreaterThanEquals);
-exact!('+', '=', plus_equals, Tok::PlusEquals);
-exact!('-', '=', minus_equals, Tok::MinusEquals);
-exact!('*', '=', asterisk_equals, Tok::AsteriskEquals);
-exact!('/', '=', slash_equals, Tok::SlashEquals);
-exact!('.', '.', double_full_stop, Tok::DoubleFullStop);
-exact!('.', full_stop, Tok::FullStop);
-exact!('=', equals, Tok::Equals);
-exact!('+', plus, Tok::Plus);
-exact!('-', minus, Tok::Minus);
-exact!('*', asterisk, Tok::Asterisk);
-exact!('/', slash, Tok::Slash);
-exact!('|', vertical_bar, Tok::VerticalBar);
-exact!(':', colon, Tok::Colon);
-exact!('^', caret, Tok::Caret);
-exact!('(', paren_left, Tok::ParenLeft);
-exact!(')', paren_right, Tok::ParenRight);
-exact!('[', square_bracket_left, Tok::SquareBracketLeft);
-exact!(']', square_bracket_right, Tok::SquareBracketRight);
-exact!('<', less_than, Tok::LessThan);
-exact!('>', greater_than, Tok::GreaterThan);
-exact!('{', curly_bracket_left, Tok::CurlyBracketLeft);
-exact!('}', curly_backet_right, Tok::CurlyBracketRight);
-
-const MATCHERS: &[fn(advancer: &mut CharAdvancer) -> TokMatch] = &[
-   space_line_end,
-   double_asterisk,
-   double_equals,
-   exclamation_equals,
-   less_than_equals,
-   greater_than_equals,
-   plus_equals,
-   minus_equals,
-   asterisk_equals,
-   slash_equals,
-   double_full_stop,
-   equals,
-   plus,
-   minus,
-   asterisk,
-   slash,
-   vertical_bar,
-   colon,
-   caret,
-   paren_left,
-   paren_right,
-   square_bracket_left,
-   square_bracket_right,
-   less_than,
-   greater_than,
-   curly_bracket_left,
-   curly_backet_right,
-   identifier,
-   digits,
-   full_stop,
-];
+const MATCHERS: &[fn(advancer: &mut CharAdvancer) -> TokMatch] =
+   &[space_line_end, comment, punctuation, identifier, digits];
 
 fn run_matchers(advancer: &mut CharAdvancer) -> TokMatch {
    for matcher in MATCHERS {
@@ -314,8 +302,6 @@ struct Tokenizer<'s> {
    toks: Vec<Tok>,
    toks_meta: Vec<TokMeta>,
    end: usize,
-   line: usize,
-   col: usize,
    advancer: CharAdvancer<'s>,
    line_starts: Vec<usize>,
 }
@@ -327,17 +313,13 @@ impl<'s> Tokenizer<'s> {
       let line_starts = vec![0];
 
       let end = 0;
-      let line = 1;
-      let col = 1;
 
-      let advancer = CharAdvancer::new(chars);
+      let advancer = CharAdvancer::with_lines(chars);
 
       Tokenizer {
          toks,
          toks_meta,
          end,
-         line,
-         col,
          advancer,
          line_starts,
       }
@@ -358,22 +340,37 @@ impl<'s> Tokenizer<'s> {
       self.toks.push(tok);
 
       let span = end - self.end;
+      let location = self.location(self.end);
 
       self.toks_meta.push(TokMeta {
          span,
          end,
-         line: self.line,
-         col: self.col,
+         line: location.line,
+         col: location.col,
       });
 
-      self.col += span;
       self.end = end;
    }
 
-   fn tokenize(mut self) -> Self {
+   fn location(&self, pos: usize) -> Location {
+      self.advancer.location(pos).unwrap()
+   }
+
+   fn error(&self, issue: TokenizeIssue, pos: usize) -> TokenizeError {
+      let location = self.location(pos);
+
+      TokenizeError {
+         issue,
+         line: location.line,
+         col: location.col,
+      }
+   }
+
+   fn tokenize(mut self) -> Result<Self, TokenizeError> {
       while !self.advancer.completed() {
-         if self.match_string().is_none() {
-            self.match_tok()
+         match self.match_string() {
+            Some(string) => string?,
+            None => self.match_tok()?,
          }
       }
 
@@ -390,29 +387,28 @@ impl<'s> Tokenizer<'s> {
          self.line_starts.pop();
       }
 
-      self
+      Ok(self)
    }
 
-   fn match_tok(&mut self) {
-      if let Some((tok, end)) = run_matchers(&mut self.advancer) {
-         let after_new_line = tok == Tok::LineEnd;
+   fn match_tok(&mut self) -> Result<(), TokenizeError> {
+      let (tok, end) = match run_matchers(&mut self.advancer) {
+         Some(matched) => matched,
+         None => return Err(self.error(TokenizeIssue::Unrecognized, self.end)),
+      };
 
-         self.push(tok, end);
+      let after_new_line = tok == Tok::LineEnd;
 
-         if after_new_line {
-            self.line += 1;
-            self.col = 1;
-            self.line_starts.push(self.toks.len());
-         }
-      } else {
-         panic!(
-            "Unrecognized token at line: {}, col: {}",
-            self.line, self.col
-         );
+      self.push(tok, end);
+
+      if after_new_line {
+         self.line_starts.push(self.toks.len());
       }
+
+      Ok(())
    }
 
-   fn match_string(&mut self) -> Option<()> {
+   /// Matches a string, `None` when there is none at the current position.
+   fn match_string(&mut self) -> Option<Result<(), TokenizeError>> {
       self.advancer.one('\'')?;
 
       let start = self.advancer.pos();
@@ -429,13 +425,8 @@ impl<'s> Tokenizer<'s> {
                break;
             }
             '\n' | '\r' => {
-               self.end = self.advancer.pos();
-               self.col += self.end - start;
-
-               panic!(
-                  "New line in string at line: {}, col: {}",
-                  self.line, self.col
-               );
+               let pos = self.advancer.pos() - 1;
+               return Some(Err(self.error(TokenizeIssue::LineEndInString, pos)));
             }
             _ => {}
          }
@@ -451,12 +442,188 @@ impl<'s> Tokenizer<'s> {
 
       self.advancer.consume();
 
-      Some(())
+      Some(Ok(()))
    }
 }
 
+/// Panics when `chars` cannot be tokenized, see `try_tokenize`.
 pub fn tokenize(chars: &[char]) -> (Vec<Tok>, Vec<TokMeta>, Vec<usize>) {
-   Tokenizer::new(chars).tokenize().destructure()
+   try_tokenize(chars).unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// Tokenizes `chars`, failing at the first char that starts no token or a
+/// line end inside a string.
+pub fn try_tokenize(chars: &[char]) -> Result<Tokens, TokenizeError> {
+   Ok(Tokenizer::new(chars).tokenize()?.destructure())
+}
+
+/// Tokenizer working directly on UTF-8 input, producing the same output as
+/// `Tokenizer` without decoding the source into chars first. Runs of spaces,
+/// identifier characters, digits and string bodies are scanned a word at a
+/// time; punctuation is decoded and handed to `Dispatch`.
+struct StrTokenizer<'s> {
+   source: &'s str,
+   toks: Vec<Tok>,
+   toks_meta: Vec<TokMeta>,
+   line_starts: Vec<usize>,
+   pos: usize,
+   end: usize,
+   line: usize,
+   line_start: usize,
+}
+
+impl<'s> StrTokenizer<'s> {
+   fn new(source: &'s str) -> Self {
+      StrTokenizer {
+         source,
+         toks: vec![],
+         toks_meta: vec![],
+         line_starts: vec![0],
+         pos: 0,
+         end: 0,
+         line: 1,
+         line_start: 0,
+      }
+   }
+
+   fn destructure(self) -> (Vec<Tok>, Vec<TokMeta>, Vec<usize>) {
+      (self.toks, self.toks_meta, self.line_starts)
+   }
+
+   fn push(&mut self, tok: Tok, bytes: usize, span: usize) {
+      let after_new_line = tok == Tok::LineEnd;
+
+      self.toks.push(tok);
+
+      self.toks_meta.push(TokMeta {
+         span,
+         end: self.end + span,
+         line: self.line,
+         col: self.end - self.line_start + 1,
+      });
+
+      self.pos += bytes;
+      self.end += span;
+
+      if after_new_line {
+         self.line += 1;
+         self.line_start = self.end;
+         self.line_starts.push(self.toks.len());
+      }
+   }
+
+   fn tokenize(mut self) -> Self {
+      let bytes = self.source.as_bytes();
+
+      while self.pos < bytes.len() {
+         let rest = &bytes[self.pos..];
+         match rest[0] {
+            b' ' | b'\r' | b'\n' => self.space_line_end(rest),
+            b'\'' => self.string(rest),
+            b'#' => {
+               let len = rest
+                  .iter()
+                  .position(|b| *b == b'\n' || *b == b'\r')
+                  .unwrap_or(rest.len());
+               self.push(Tok::Comment, len, scan::count_chars(&rest[..len]));
+            }
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+               let len = 1 + scan::identifier(&rest[1..]);
+               self.push(try_keyword(&rest[..len]), len, len);
+            }
+            b'0'..=b'9' => {
+               let len = scan::digits(rest);
+               self.push(Tok::Digits, len, len);
+            }
+            _ => self.exact(),
+         }
+      }
+
+      if self.toks.last() != Some(&Tok::LineEnd) {
+         self.push(Tok::LineEnd, 0, 0);
+      }
+      self.line_starts.pop();
+
+      self
+   }
+
+   fn space_line_end(&mut self, rest: &[u8]) {
+      let spaces = scan::spaces(rest);
+
+      let (tok, len) = match rest.get(spaces) {
+         Some(&b'\r') if rest.get(spaces + 1) == Some(&b'\n') => (Tok::LineEnd, spaces + 2),
+         Some(&b'\r') | Some(&b'\n') => (Tok::LineEnd, spaces + 1),
+         Some(_) => (Tok::Space, spaces),
+         None => (Tok::LineEnd, spaces),
+      };
+
+      self.push(tok, len, len);
+   }
+
+   fn string(&mut self, rest: &[u8]) {
+      let mut len = 1;
+
+      loop {
+         len += scan::string_body(&rest[len..]);
+
+         match rest.get(len) {
+            Some(&b'\'') => break,
+            Some(&b'\\') => match rest.get(len + 1) {
+               Some(&b'n') | Some(&b'\'') | Some(&b'\\') | Some(&b'r') | Some(&b't')
+               | Some(&b'0') => len += 2,
+               _ => self.unrecognized(),
+            },
+            Some(_) => {
+               let col = self.end - self.line_start + 1 + scan::count_chars(&rest[..len]);
+               panic!("New line in string at line: {}, col: {}", self.line, col);
+            }
+            None => self.unrecognized(),
+         }
+      }
+
+      self.push(Tok::Apostrophe, 1, 1);
+
+      if len > 1 {
+         let span = scan::count_chars(&rest[1..len]);
+         self.push(Tok::Text, len - 1, span);
+      }
+
+      self.push(Tok::Apostrophe, 1, 1);
+   }
+
+   fn exact(&mut self) {
+      let mut window = ['\0'; MAX_PATTERN];
+      let mut count = 0;
+      for (i, c) in self.source[self.pos..]
+         .chars()
+         .take(MAX_PATTERN)
+         .enumerate()
+      {
+         window[i] = c;
+         count += 1;
+      }
+
+      match punctuation(&mut CharAdvancer::new(&window[..count])) {
+         Some((tok, span)) => {
+            let len = window[..span].iter().map(|c| c.len_utf8()).sum();
+            self.push(tok, len, span);
+         }
+         None => self.unrecognized(),
+      }
+   }
+
+   fn unrecognized(&self) -> ! {
+      panic!(
+         "Unrecognized token at line: {}, col: {}",
+         self.line,
+         self.end - self.line_start + 1
+      );
+   }
+}
+
+/// Tokenizes UTF-8 source with the same result as `tokenize` on its chars.
+pub fn tokenize_str(source: &str) -> (Vec<Tok>, Vec<TokMeta>, Vec<usize>) {
+   StrTokenizer::new(source).tokenize().destructure()
 }
 
 #[cfg(test)]
@@ -524,20 +691,38 @@ mod tests {
    }
 
    #[test]
-   fn test_exact() {
-      m!(double_asterisk, "*");
-      m!(double_asterisk, "-**");
-      m!(asterisk, "*", Tok::Asterisk, 1);
-      m!(double_asterisk, "**", Tok::DoubleAsterisk, 2);
-      m!(double_asterisk, "****", Tok::DoubleAsterisk, 2);
+   fn test_punctuation() {
+      m!(punctuation, "-**", Tok::Minus, 1);
+      m!(punctuation, "a*");
+      m!(punctuation, " *");
+      m!(punctuation, "!");
+      m!(punctuation, "*", Tok::Asterisk, 1);
+      m!(punctuation, "**", Tok::DoubleAsterisk, 2);
+      m!(punctuation, "****", Tok::DoubleAsterisk, 2);
+      m!(punctuation, "*=", Tok::AsteriskEquals, 2);
+      m!(punctuation, "!=", Tok::ExclamationEquals, 2);
+      m!(punctuation, "...", Tok::DoubleFullStop, 2);
+      m!(punctuation, ".5", Tok::FullStop, 1);
+      m!(punctuation, "}", Tok::CurlyBracketRight, 1);
+   }
+
+   #[test]
+   fn test_punctuation_spec() {
+      assert!(Dispatch::new(PUNCTUATION, claimed).is_ok());
+   }
+
+   #[test]
+   fn test_from_name() {
+      assert_eq!(Tok::from_name("LineEnd"), Some(Tok::LineEnd));
+      assert_eq!(Tok::from_name("DoubleEquals"), Some(Tok::DoubleEquals));
+      assert_eq!(Tok::from_name("lineEnd"), None);
    }
 
    #[test]
    #[should_panic]
    #[cfg(debug_assertions)]
-   fn test_exact_empty() {
-      e!(double_asterisk);
-      e!(asterisk);
+   fn test_punctuation_empty() {
+      e!(punctuation);
    }
 
    #[test]
@@ -602,6 +787,17 @@ mod tests {
       e!(identifier);
    }
 
+   #[test]
+   fn test_comment() {
+      m!(comment, "");
+      m!(comment, " #");
+      m!(comment, "#", Tok::Comment, 1);
+      m!(comment, "# lax: indent=4", Tok::Comment, 15);
+      m!(comment, "#Я\n", Tok::Comment, 2);
+      m!(comment, "# a\r\n", Tok::Comment, 3);
+      m!(comment, "##\r", Tok::Comment, 2);
+   }
+
    #[test]
    fn test_digits() {
       m!(digits, "");
@@ -640,4 +836,99 @@ mod tests {
       string!("'aaa\\\'bbb'", 8);
       string!("'aaa\\0bbb'", 8);
    }
+
+   #[test]
+   fn test_line_col() {
+      let chars = as_chars("a\r\n  b\rc\n\nd 'e'");
+      let (toks, toks_meta, line_starts) = tokenize(&chars);
+      let positions: Vec<_> = toks
+         .iter()
+         .zip(&toks_meta)
+         .filter(|&(tok, _)| tok != &Tok::LineEnd && tok != &Tok::Space)
+         .map(|(_, meta)| (meta.line, meta.col))
+         .collect();
+      assert_eq!(
+         positions,
+         vec![(1, 1), (2, 3), (3, 1), (5, 1), (5, 3), (5, 4), (5, 5)]
+      );
+      assert_eq!(line_starts, vec![0, 2, 5, 7, 8]);
+   }
+
+   #[test]
+   #[should_panic(expected = "New line in string at line: 2, col: 4")]
+   fn test_string_line_end() {
+      tokenize(&as_chars("\n'ab\r\n'"));
+   }
+
+   #[test]
+   fn test_tokenize_errors() {
+      let error = |source: &str| {
+         let error = try_tokenize(&as_chars(source)).unwrap_err();
+         (error.issue, error.line, error.col)
+      };
+      assert_eq!(error("a Я"), (TokenizeIssue::Unrecognized, 1, 3));
+      assert_eq!(error("\n'ab\r\n'"), (TokenizeIssue::LineEndInString, 2, 4));
+      assert_eq!(error("x\n  'ab"), (TokenizeIssue::Unrecognized, 2, 4));
+      assert!(try_tokenize(&as_chars("'ab'")).is_ok());
+   }
+
+   #[test]
+   fn test_tok_advancer() {
+      let chars = as_chars("if x  ==  10\n   ret x");
+      let (toks, _, _) = tokenize(&chars);
+      let mut advancer = tok_advancer(&toks);
+      assert_eq!(advancer.peek_nth(0), Some(&Tok::If));
+      assert_eq!(advancer.peek_nth(2), Some(&Tok::DoubleEquals));
+      assert_eq!(advancer.peek_nth(4), Some(&Tok::LineEnd));
+      assert_eq!(advancer.peek_nth(5), Some(&Tok::Ret));
+      assert!(advancer.one(Tok::If).is_some());
+      assert!(advancer.one(Tok::Identifier).is_some());
+      assert!(advancer.one(Tok::Equals).is_none());
+      assert_eq!(advancer.pos(), 0);
+      advancer.one(Tok::If);
+      advancer.one(Tok::Identifier);
+      advancer.one(&[Tok::DoubleEquals, Tok::ExclamationEquals] as &[Tok]);
+      advancer.one(Tok::Digits);
+      advancer.one(Tok::LineEnd);
+      assert_eq!(advancer.consume(), 8);
+      advancer.one(Tok::Ret);
+      advancer.one(Tok::Identifier);
+      advancer.one(Tok::LineEnd);
+      advancer.consume();
+      assert!(advancer.completed());
+   }
+
+   #[test]
+   fn test_tokenize_str() {
+      for source in &[
+         "",
+         " ",
+         "x",
+         "x  ",
+         "\n\n",
+         "a\r\n  b\rc\n\nd 'e'",
+         "if x  ==  10\n   ret x\n",
+         "a**=b..c.5 1.x|y^z:(1)[2]{3}<4>5<=>=!=+=-=/=",
+         "_NAME_a100 efx ef el fn or and for not ret loop true break false match",
+         "s = 'ЯaЯaЯ' + 'y̆' + '' + 'aaa\\n\\'bbb\\\\' ",
+         "# Я comment\nx = 1  # 'not a string'\r\n   #\n#",
+         include_str!("../lax/tokenize.lax"),
+         include_str!("../lax/block.lax"),
+      ] {
+         let chars = as_chars(source);
+         assert_eq!(tokenize_str(source), tokenize(&chars), "{:?}", source);
+      }
+   }
+
+   #[test]
+   #[should_panic(expected = "New line in string at line: 2, col: 4")]
+   fn test_str_string_line_end() {
+      tokenize_str("\n'ab\r\n'");
+   }
+
+   #[test]
+   #[should_panic(expected = "Unrecognized token at line: 1, col: 3")]
+   fn test_str_unrecognized() {
+      tokenize_str("a Я");
+   }
 }
@@ -0,0 +1,121 @@
+use std::io::{self, Write};
+
+use tokenize::Tok;
+
+/// Hook receiving the progress of a parser. Every `enter` is followed by
+/// exactly one `exit` or `fail` of the same rule, with the events of the
+/// nested rules in between.
+pub trait Trace {
+   /// Rule `rule` starts at token `pos`.
+   fn enter(&mut self, _rule: &str, _pos: usize) {}
+
+   /// Rule `rule` entered at `start` matched the tokens up to `end`.
+   fn exit(&mut self, _rule: &str, _start: usize, _end: usize) {}
+
+   /// Token `tok` at `pos` was consumed.
+   fn matched(&mut self, _tok: &Tok, _pos: usize) {}
+
+   /// Rule `rule` entered at `start` did not match, failing at `pos`.
+   fn fail(&mut self, _rule: &str, _start: usize, _pos: usize) {}
+}
+
+/// Prints every event on its own line, indented by the rule nesting:
+///
+/// ```text
+/// [000] statement >>
+/// [000] ..assign >>
+/// [000] ....Identifier
+/// [002] ....Equals
+/// [000] ..assign [000-005] <<
+/// [000] statement [000-005] <<
+/// ```
+pub struct Printer<W: Write> {
+   writer: W,
+   depth: usize,
+}
+
+impl Printer<io::Stdout> {
+   pub fn stdout() -> Self {
+      Printer::new(io::stdout())
+   }
+}
+
+impl<W: Write> Printer<W> {
+   pub fn new(writer: W) -> Self {
+      Printer { writer, depth: 0 }
+   }
+
+   pub fn into_inner(self) -> W {
+      self.writer
+   }
+
+   /// Output is best effort, a debugging aid should not abort the parse
+   /// when the writer fails.
+   fn line(&mut self, pos: usize, text: &str) {
+      let _ = writeln!(
+         self.writer,
+         "[{:03}] {}{}",
+         pos,
+         "..".repeat(self.depth),
+         text
+      );
+   }
+}
+
+impl<W: Write> Trace for Printer<W> {
+   fn enter(&mut self, rule: &str, pos: usize) {
+      self.line(pos, &format!("{} >>", rule));
+      self.depth += 1;
+   }
+
+   fn exit(&mut self, rule: &str, start: usize, end: usize) {
+      self.depth -= 1;
+      self.line(start, &format!("{} [{:03}-{:03}] <<", rule, start, end));
+   }
+
+   fn matched(&mut self, tok: &Tok, pos: usize) {
+      self.line(pos, &format!("{:?}", tok));
+   }
+
+   fn fail(&mut self, rule: &str, _start: usize, pos: usize) {
+      self.depth -= 1;
+      self.line(pos, &format!("{} !!", rule));
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_printer() {
+      let mut printer = Printer::new(Vec::new());
+
+      printer.enter("statement", 0);
+      printer.enter("assign", 0);
+      printer.matched(&Tok::Identifier, 0);
+      printer.matched(&Tok::Equals, 2);
+      printer.exit("assign", 0, 5);
+      printer.enter("expression", 6);
+      printer.fail("expression", 6, 6);
+      printer.exit("statement", 0, 5);
+
+      let output = String::from_utf8(printer.into_inner()).unwrap();
+
+      assert_eq!(
+         output,
+         indoc!(
+            "
+            [000] statement >>
+            [000] ..assign >>
+            [000] ....Identifier
+            [002] ....Equals
+            [000] ..assign [000-005] <<
+            [006] ..expression >>
+            [006] ..expression !!
+            [000] statement [000-005] <<
+            "
+         )
+      );
+   }
+}